@@ -2,9 +2,12 @@
 pub enum Expression {
     Ident(String),
     Int(String),
+    Float(String),
     Str(String),
     Bool(String),
+    Null,
     Array(Vec<Box<Expression>>),
+    Hash(Vec<(Box<Expression>, Box<Expression>)>),
     Prefix {
         operator: String,
         expr: Box<Expression>,
@@ -27,6 +30,12 @@ pub enum Expression {
         function: Box<Expression>,
         arguments: Vec<Box<Expression>>,
     },
+    Block(Box<Statement>),    // value of the last statement
+    Slice {
+        target: Box<Expression>,
+        start: Option<Box<Expression>>,
+        end: Option<Box<Expression>>,
+    },
 }
 
 #[derive(PartialEq, Eq, Debug, Clone)]
@@ -35,7 +44,25 @@ pub enum Statement {
         ident: Expression,
         expr: Expression,
     },
+    Assign {
+        ident: Expression,
+        expr: Expression,
+    },
     Return(Expression),
+    While {
+        condition: Expression,
+        body: Box<Statement>,
+    },
+    DoWhile {
+        body: Box<Statement>,
+        condition: Expression,
+    },
+    For {
+        init: Box<Statement>,
+        condition: Expression,
+        update: Box<Statement>,
+        body: Box<Statement>,
+    },
     Expr(Expression),
     Block(Vec<Box<Statement>>),
 }
@@ -0,0 +1,324 @@
+use std::cmp::Ordering;
+
+use crate::object::Object;
+use crate::object::float_operands;
+use crate::object::MAX_ARRAY_LEN;
+
+const NULL: Object = Object::Null;
+
+// Builtin functions are referred to by name in the evaluator and by index in the VM.
+pub const BUILTINS: [&str; 15] = [
+    "len", "first", "last", "rest", "push", "puts", "type", "int", "str", "range", "join", "contains",
+    "abs", "min", "max",
+];
+
+pub fn call_builtin(name: &str, arguments: Vec<Object>) -> Object {
+    match name {
+        "len" => builtin_len(arguments),
+        "first" => builtin_first(arguments),
+        "last" => builtin_last(arguments),
+        "rest" => builtin_rest(arguments),
+        "push" => builtin_push(arguments),
+        "puts" => builtin_puts(arguments),
+        "type" => builtin_type(arguments),
+        "int" => builtin_int(arguments),
+        "str" => builtin_str(arguments),
+        "range" => builtin_range(arguments),
+        "join" => builtin_join(arguments),
+        "contains" => builtin_contains(arguments),
+        "abs" => builtin_abs(arguments),
+        "min" => builtin_extreme("min", arguments, Ordering::Less),
+        "max" => builtin_extreme("max", arguments, Ordering::Greater),
+        name => Object::Error(format!("Unknown builtin {}.", name)),
+    }
+}
+
+pub fn check_arguments(name: &str, arguments: &[Object], num_paras: usize) -> Option<Object> {
+    // Return an error object if the builtin is called with a wrong number of arguments.
+    if arguments.len() == num_paras {
+        None
+    } else {
+        Some(Object::Error(format!("{} expects {} arguments, get {}.",
+                                   name, num_paras, arguments.len())))
+    }
+}
+
+fn builtin_len(arguments: Vec<Object>) -> Object {
+    if let Some(err) = check_arguments("len", &arguments, 1) {
+        return err;
+    }
+    match &arguments[0] {
+        Object::Str(s) => Object::Int(s.chars().count() as i64),
+        Object::Array(vec) => Object::Int(vec.len() as i64),
+        obj => Object::Error(format!("len expects Object::Str or Object::Array, get {}.", obj)),
+    }
+}
+
+// first, last and rest all return null on an empty array, rest never returns [].
+fn builtin_first(arguments: Vec<Object>) -> Object {
+    if let Some(err) = check_arguments("first", &arguments, 1) {
+        return err;
+    }
+    match &arguments[0] {
+        Object::Array(vec) => match vec.first() {
+            Some(obj) => (**obj).clone(),
+            None => NULL,
+        },
+        obj => Object::Error(format!("first expects Object::Array, get {}.", obj)),
+    }
+}
+
+fn builtin_last(arguments: Vec<Object>) -> Object {
+    if let Some(err) = check_arguments("last", &arguments, 1) {
+        return err;
+    }
+    match &arguments[0] {
+        Object::Array(vec) => match vec.last() {
+            Some(obj) => (**obj).clone(),
+            None => NULL,
+        },
+        obj => Object::Error(format!("last expects Object::Array, get {}.", obj)),
+    }
+}
+
+fn builtin_rest(arguments: Vec<Object>) -> Object {
+    if let Some(err) = check_arguments("rest", &arguments, 1) {
+        return err;
+    }
+    match &arguments[0] {
+        Object::Array(vec) if vec.is_empty() => NULL,
+        Object::Array(vec) => Object::Array(vec[1..].to_vec()),
+        obj => Object::Error(format!("rest expects Object::Array, get {}.", obj)),
+    }
+}
+
+fn builtin_push(arguments: Vec<Object>) -> Object {
+    if let Some(err) = check_arguments("push", &arguments, 2) {
+        return err;
+    }
+    match &arguments[0] {
+        Object::Array(vec) => {
+            let mut vec = vec.clone();
+            vec.push(Box::new(arguments[1].clone()));
+            Object::Array(vec)
+        },
+        obj => Object::Error(format!("push expects Object::Array, get {}.", obj)),
+    }
+}
+
+fn builtin_puts(arguments: Vec<Object>) -> Object {
+    for obj in arguments.iter() {
+        println!("{}", obj);
+    }
+    NULL
+}
+
+fn builtin_type(arguments: Vec<Object>) -> Object {
+    if let Some(err) = check_arguments("type", &arguments, 1) {
+        return err;
+    }
+    Object::Str(String::from(type_name(&arguments[0])))
+}
+
+fn builtin_int(arguments: Vec<Object>) -> Object {
+    if let Some(err) = check_arguments("int", &arguments, 1) {
+        return err;
+    }
+    match &arguments[0] {
+        Object::Int(v) => Object::Int(*v),
+        Object::Str(s) => match s.trim().parse() {
+            Ok(v) => Object::Int(v),
+            Err(_) => Object::Error(format!("Cannot convert {:?} to an integer.", s)),
+        },
+        obj => Object::Error(format!("int expects Object::Int or Object::Str, get {}.", obj)),
+    }
+}
+
+fn builtin_str(arguments: Vec<Object>) -> Object {
+    if let Some(err) = check_arguments("str", &arguments, 1) {
+        return err;
+    }
+    Object::Str(format!("{}", arguments[0]))
+}
+
+fn builtin_range(arguments: Vec<Object>) -> Object {
+    // range(n) counts from 0, and an empty array is returned if start is not below end.
+    let (start, end) = match arguments.as_slice() {
+        [Object::Int(end)] => (0, *end),
+        [Object::Int(start), Object::Int(end)] => (*start, *end),
+        [_] | [_, _] => {
+            let obj = arguments.iter().find(|obj| !matches!(obj, Object::Int(_))).unwrap();
+            return Object::Error(format!("range expects Object::Int, get {}.", obj));
+        },
+        _ => return Object::Error(format!("range expects 1 or 2 arguments, get {}.", arguments.len())),
+    };
+    let too_long = match end.checked_sub(start) {
+        Some(len) => len > MAX_ARRAY_LEN as i64,
+        None => end > start,
+    };
+    if too_long {
+        return Object::Error(String::from("Array too long."));
+    }
+    Object::Array((start..end).map(|v| Box::new(Object::Int(v))).collect())
+}
+
+fn builtin_join(arguments: Vec<Object>) -> Object {
+    if let Some(err) = check_arguments("join", &arguments, 2) {
+        return err;
+    }
+    match (&arguments[0], &arguments[1]) {
+        (Object::Array(vec), Object::Str(sep)) => {
+            let mut strs = Vec::new();
+            for obj in vec.iter() {
+                match obj.as_ref() {
+                    Object::Str(s) => strs.push(s.as_str()),
+                    obj => return Object::Error(format!("join expects an array of Object::Str, get {}.", obj)),
+                }
+            }
+            Object::Str(strs.join(sep))
+        },
+        (Object::Array(_), obj) => Object::Error(format!("join expects Object::Str, get {}.", obj)),
+        (obj, _) => Object::Error(format!("join expects Object::Array, get {}.", obj)),
+    }
+}
+
+fn builtin_contains(arguments: Vec<Object>) -> Object {
+    // Check whether an array has an equal element, or whether a hash has the key.
+    if let Some(err) = check_arguments("contains", &arguments, 2) {
+        return err;
+    }
+    match (&arguments[0], &arguments[1]) {
+        (Object::Array(vec), obj) => Object::Bool(vec.iter().any(|elem| **elem == *obj)),
+        (Object::Hash(hash), key) => match key.hashable() {
+            Some(key) => Object::Bool(hash.contains_key(&key)),
+            None => Object::Error(format!("Unusable as hash key: {}.", key)),
+        },
+        (obj, _) => Object::Error(format!("contains expects Object::Array or Object::Hash, get {}.", obj)),
+    }
+}
+
+fn builtin_abs(arguments: Vec<Object>) -> Object {
+    if let Some(err) = check_arguments("abs", &arguments, 1) {
+        return err;
+    }
+    match &arguments[0] {
+        Object::Int(v) => match v.checked_abs() {
+            Some(v) => Object::Int(v),
+            None => Object::Error(String::from("Integer overflow.")),
+        },
+        Object::Float(v) => Object::Float(v.abs()),
+        obj => Object::Error(format!("abs expects Object::Int or Object::Float, get {}.", obj)),
+    }
+}
+
+fn builtin_extreme(name: &str, arguments: Vec<Object>, wanted: Ordering) -> Object {
+    // min and max keep the first of equal values, and compare an int with a float as floats.
+    if arguments.is_empty() {
+        return Object::Error(format!("{} expects at least 1 argument, get 0.", name));
+    }
+    let mut result = &arguments[0];
+    for obj in arguments.iter() {
+        let ordering = match (obj, result) {
+            (Object::Int(v), Object::Int(r)) => Some(v.cmp(r)),
+            (Object::Int(_), _) | (Object::Float(_), _) => {
+                float_operands(obj, result).and_then(|(v, r)| v.partial_cmp(&r))
+            },
+            (obj, _) => return Object::Error(format!("{} expects Object::Int or Object::Float, get {}.", name, obj)),
+        };
+        if ordering == Some(wanted) {
+            result = obj;
+        }
+    }
+    result.clone()
+}
+
+fn type_name(obj: &Object) -> &'static str {
+    match obj {
+        Object::Int(_) => "int",
+        Object::Float(_) => "float",
+        Object::Str(_) => "str",
+        Object::Bool(_) => "bool",
+        Object::Null => "null",
+        Object::Return(obj) => type_name(obj),
+        Object::Error(_) => "error",
+        Object::Array(_) => "array",
+        Object::Hash(_) => "hash",
+        Object::Function { .. } | Object::Builtin(_) | Object::CompiledFunction { .. } => "function",
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn builtins() {
+        for name in BUILTINS.iter() {
+            // Every listed builtin is dispatched, so none is reported as unknown.
+            assert_ne!(call_builtin(name, vec!()), Object::Error(format!("Unknown builtin {}.", name)));
+        }
+        assert_eq!(call_builtin("len", vec!(Object::Str(String::from("abc")))), Object::Int(3));
+        assert_eq!(call_builtin("foo", vec!()), Object::Error(String::from("Unknown builtin foo.")));
+    }
+
+    #[test]
+    fn builtins_empty_array() {
+        for name in ["first", "last", "rest"].iter() {
+            assert_eq!(call_builtin(name, vec!(Object::Array(vec!()))), NULL, "{}", name);
+        }
+        let one = || Object::Array(vec!(Box::new(Object::Int(1))));
+        assert_eq!(call_builtin("first", vec!(one())), Object::Int(1));
+        assert_eq!(call_builtin("last", vec!(one())), Object::Int(1));
+        assert_eq!(call_builtin("rest", vec!(one())), Object::Array(vec!()));
+    }
+
+    #[test]
+    fn builtins_range() {
+        let ints = |vec: Vec<i64>| Object::Array(vec.into_iter().map(|v| Box::new(Object::Int(v))).collect());
+        let test_array = [
+            (vec!(Object::Int(3)), ints(vec!(0, 1, 2))),
+            (vec!(Object::Int(2), Object::Int(5)), ints(vec!(2, 3, 4))),
+            (vec!(Object::Int(0)), ints(vec!())),
+            (vec!(Object::Int(-2)), ints(vec!())),
+            (vec!(Object::Int(5), Object::Int(2)), ints(vec!())),
+            (vec!(Object::Int(-2), Object::Int(1)), ints(vec!(-2, -1, 0))),
+            (vec!(Object::Int(0), Object::Int(i64::MAX)), Object::Error(String::from("Array too long."))),
+            (vec!(Object::Int(i64::MIN), Object::Int(i64::MAX)), Object::Error(String::from("Array too long."))),
+            (vec!(Object::Int(i64::MAX), Object::Int(i64::MIN)), ints(vec!())),
+            (vec!(), Object::Error(String::from("range expects 1 or 2 arguments, get 0."))),
+            (vec!(Object::Int(1), Object::Str(String::from("a"))),
+             Object::Error(String::from("range expects Object::Int, get a."))),
+        ];
+        for (arguments, expected) in test_array.iter() {
+            assert_eq!(call_builtin("range", arguments.clone()), *expected);
+        }
+    }
+
+    #[test]
+    fn builtins_numeric() {
+        let test_array = [
+            ("abs", vec!(Object::Int(-3)), Object::Int(3)),
+            ("abs", vec!(Object::Int(3)), Object::Int(3)),
+            ("abs", vec!(Object::Float(-1.5)), Object::Float(1.5)),
+            ("abs", vec!(Object::Int(i64::MIN)), Object::Error(String::from("Integer overflow."))),
+            ("abs", vec!(Object::Bool(true)),
+             Object::Error(String::from("abs expects Object::Int or Object::Float, get true."))),
+            ("min", vec!(Object::Int(3), Object::Int(1), Object::Int(2)), Object::Int(1)),
+            ("max", vec!(Object::Int(3), Object::Int(1), Object::Int(2)), Object::Int(3)),
+            ("min", vec!(Object::Int(7)), Object::Int(7)),
+            ("min", vec!(Object::Int(2), Object::Float(1.5)), Object::Float(1.5)),
+            ("max", vec!(Object::Float(2.0), Object::Int(2)), Object::Float(2.0)),
+            ("min", vec!(), Object::Error(String::from("min expects at least 1 argument, get 0."))),
+            ("max", vec!(), Object::Error(String::from("max expects at least 1 argument, get 0."))),
+            ("max", vec!(Object::Int(1), Object::Str(String::from("a"))),
+             Object::Error(String::from("max expects Object::Int or Object::Float, get a."))),
+            ("min", vec!(Object::Null, Object::Int(1)),
+             Object::Error(String::from("min expects Object::Int or Object::Float, get Null."))),
+        ];
+        for (name, arguments, expected) in test_array.iter() {
+            assert_eq!(call_builtin(name, arguments.clone()), *expected, "{}", name);
+        }
+    }
+}
@@ -1,41 +1,373 @@
 use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
 
 use crate::object::Object;
+use crate::builtins::BUILTINS;
 
 #[derive(PartialEq, Eq, Debug, Clone)]
 pub enum Code {
-    Constant(Object),
+    Constant(usize),    // index into the constant pool
     Pop,
     Add,
     Sub,
     Mul,
     Div,
+    Mod,
     True,
     False,
     Equal,
     NotEqual,
     GreaterThan,
     LessThan,
+    And,
+    Or,
     Minus,
     Bang,
-    JumpNotTruthy(usize),
-    Jump(usize),
+    JumpNotTruthy(usize),    // absolute target
+    Jump(usize),    // absolute target
     Null,
     SetGlobal(usize),
     GetGlobal(usize),
     Array(usize),
+    Hash(usize),    // number of key-value pairs
     Index,
     ReturnValue,
     Return,
     Call(usize),
     SetLocal(usize),
     GetLocal(usize),
+    CurrentFunction,
+    GetBuiltin(usize),    // index into BUILTINS
+    BitAnd,
+    BitOr,
+    BitXor,
+    ShiftLeft,
+    ShiftRight,
+    BitNot,
+    Dup,    // push a copy of the top of the stack
+    Plus,
+}
+
+impl fmt::Display for Code {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Code::Constant(operand) => write!(f, "Constant {}", operand),
+            Code::JumpNotTruthy(target) => write!(f, "JumpNotTruthy {:04}", target),
+            Code::Jump(target) => write!(f, "Jump {:04}", target),
+            Code::SetGlobal(operand) => write!(f, "SetGlobal {}", operand),
+            Code::GetGlobal(operand) => write!(f, "GetGlobal {}", operand),
+            Code::Array(operand) => write!(f, "Array {}", operand),
+            Code::Hash(operand) => write!(f, "Hash {}", operand),
+            Code::Call(operand) => write!(f, "Call {}", operand),
+            Code::SetLocal(operand) => write!(f, "SetLocal {}", operand),
+            Code::GetLocal(operand) => write!(f, "GetLocal {}", operand),
+            Code::GetBuiltin(operand) => write!(f, "GetBuiltin {}", operand),
+            code => write!(f, "{:?}", code),
+        }
+    }
+}
+
+pub fn disassemble(code: &[Code], constants: &[Object]) -> String {
+    let mut output = String::new();
+    disassemble_into(code, constants, 0, &mut output);
+    output
+}
+
+fn disassemble_into(code: &[Code], constants: &[Object], depth: usize, output: &mut String) {
+    let indent = "    ".repeat(depth);
+    for (index, c) in code.iter().enumerate() {
+        match c {
+            Code::Constant(operand) => match &constants[*operand] {
+                Object::CompiledFunction { instructions, num_locals, num_paras } => {
+                    output.push_str(&format!("{}{:04} Constant {} (compiled function, locals {}, parameters {})\n",
+                                             indent, index, operand, num_locals, num_paras));
+                    disassemble_into(instructions, constants, depth+1, output);
+                },
+                Object::Str(value) => {
+                    output.push_str(&format!("{}{:04} Constant {} ({:?})\n", indent, index, operand, value));
+                },
+                obj => output.push_str(&format!("{}{:04} Constant {} ({})\n", indent, index, operand, obj)),
+            },
+            c => output.push_str(&format!("{}{:04} {}\n", indent, index, c)),
+        }
+    }
+}
+
+// Serialized bytecode starts with this header, followed by the constants and the instructions.
+pub const MAGIC: &[u8] = b"MBC\x02";
+
+pub fn serialize(code: &[Code], constants: &[Object]) -> Result<Vec<u8>, String> {
+    let mut bytes = MAGIC.to_vec();
+    write_usize(&mut bytes, constants.len());
+    for obj in constants.iter() {
+        write_object(&mut bytes, obj)?;
+    }
+    write_code(&mut bytes, code);
+    Ok(bytes)
+}
+
+pub fn deserialize(bytes: &[u8]) -> Result<(Vec<Code>, Vec<Object>), String> {
+    if !bytes.starts_with(MAGIC) {
+        return Err(String::from("Invalid bytecode header."));
+    }
+    let mut reader = Reader { bytes, pos: MAGIC.len() };
+    let mut constants = Vec::new();
+    for _ in 0..reader.read_usize()? {
+        constants.push(reader.read_object()?);
+    }
+    let code = reader.read_code()?;
+    if reader.pos != bytes.len() {
+        return Err(String::from("Unexpected bytes after the instructions."));
+    }
+    Ok((code, constants))
+}
+
+fn write_usize(bytes: &mut Vec<u8>, value: usize) {
+    bytes.extend_from_slice(&(value as u32).to_le_bytes());
+}
+
+fn write_str(bytes: &mut Vec<u8>, value: &str) {
+    write_usize(bytes, value.len());
+    bytes.extend_from_slice(value.as_bytes());
+}
+
+fn write_object(bytes: &mut Vec<u8>, obj: &Object) -> Result<(), String> {
+    match obj {
+        Object::Int(v) => {
+            bytes.push(0);
+            bytes.extend_from_slice(&v.to_le_bytes());
+        },
+        Object::Float(v) => {
+            bytes.push(1);
+            bytes.extend_from_slice(&v.to_le_bytes());
+        },
+        Object::Str(v) => {
+            bytes.push(2);
+            write_str(bytes, v);
+        },
+        Object::Bool(v) => bytes.extend_from_slice(&[3, *v as u8]),
+        Object::Null => bytes.push(4),
+        Object::Array(array) => {
+            bytes.push(5);
+            write_usize(bytes, array.len());
+            for obj in array.iter() {
+                write_object(bytes, obj)?;
+            }
+        },
+        Object::Hash(hash) => {
+            bytes.push(6);
+            write_usize(bytes, hash.len());
+            for (key, value) in hash.iter() {
+                write_object(bytes, &key.object())?;
+                write_object(bytes, value)?;
+            }
+        },
+        Object::Builtin(name) => {
+            bytes.push(7);
+            write_str(bytes, name);
+        },
+        Object::CompiledFunction { instructions, num_locals, num_paras } => {
+            bytes.push(8);
+            write_usize(bytes, *num_locals);
+            write_usize(bytes, *num_paras);
+            write_code(bytes, instructions);
+        },
+        obj => return Err(format!("Cannot serialize {:?}.", obj)),
+    }
+    Ok(())
+}
+
+fn write_code(bytes: &mut Vec<u8>, code: &[Code]) {
+    write_usize(bytes, code.len());
+    for c in code.iter() {
+        let (opcode, operand) = match c {
+            Code::Constant(operand) => (0, Some(operand)),
+            Code::Pop => (1, None),
+            Code::Add => (2, None),
+            Code::Sub => (3, None),
+            Code::Mul => (4, None),
+            Code::Div => (5, None),
+            Code::Mod => (6, None),
+            Code::True => (7, None),
+            Code::False => (8, None),
+            Code::Equal => (9, None),
+            Code::NotEqual => (10, None),
+            Code::GreaterThan => (11, None),
+            Code::LessThan => (12, None),
+            Code::And => (13, None),
+            Code::Or => (14, None),
+            Code::Minus => (15, None),
+            Code::Bang => (16, None),
+            Code::JumpNotTruthy(operand) => (17, Some(operand)),
+            Code::Jump(operand) => (18, Some(operand)),
+            Code::Null => (19, None),
+            Code::SetGlobal(operand) => (20, Some(operand)),
+            Code::GetGlobal(operand) => (21, Some(operand)),
+            Code::Array(operand) => (22, Some(operand)),
+            Code::Hash(operand) => (23, Some(operand)),
+            Code::Index => (24, None),
+            Code::ReturnValue => (25, None),
+            Code::Return => (26, None),
+            Code::Call(operand) => (27, Some(operand)),
+            Code::SetLocal(operand) => (28, Some(operand)),
+            Code::GetLocal(operand) => (29, Some(operand)),
+            Code::CurrentFunction => (30, None),
+            Code::GetBuiltin(operand) => (31, Some(operand)),
+            Code::BitAnd => (32, None),
+            Code::BitOr => (33, None),
+            Code::BitXor => (34, None),
+            Code::ShiftLeft => (35, None),
+            Code::ShiftRight => (36, None),
+            Code::BitNot => (37, None),
+            Code::Dup => (38, None),
+            Code::Plus => (39, None),
+        };
+        bytes.push(opcode);
+        if let Some(operand) = operand {
+            write_usize(bytes, *operand);
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn read_bytes(&mut self, n: usize) -> Result<&'a [u8], String> {
+        if self.pos + n > self.bytes.len() {
+            return Err(String::from("Unexpected end of bytecode."));
+        }
+        self.pos += n;
+        Ok(&self.bytes[self.pos-n..self.pos])
+    }
+
+    fn read_u8(&mut self) -> Result<u8, String> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    fn read_usize(&mut self) -> Result<usize, String> {
+        let mut buf = [0; 4];
+        buf.copy_from_slice(self.read_bytes(4)?);
+        Ok(u32::from_le_bytes(buf) as usize)
+    }
+
+    fn read_str(&mut self) -> Result<String, String> {
+        let len = self.read_usize()?;
+        match String::from_utf8(self.read_bytes(len)?.to_vec()) {
+            Ok(s) => Ok(s),
+            Err(_) => Err(String::from("Invalid UTF-8 string in bytecode.")),
+        }
+    }
+
+    fn read_object(&mut self) -> Result<Object, String> {
+        let obj = match self.read_u8()? {
+            0 => {
+                let mut buf = [0; 8];
+                buf.copy_from_slice(self.read_bytes(8)?);
+                Object::Int(i64::from_le_bytes(buf))
+            },
+            1 => {
+                let mut buf = [0; 8];
+                buf.copy_from_slice(self.read_bytes(8)?);
+                Object::Float(f64::from_le_bytes(buf))
+            },
+            2 => Object::Str(self.read_str()?),
+            3 => Object::Bool(self.read_u8()? != 0),
+            4 => Object::Null,
+            5 => {
+                let mut array = Vec::new();
+                for _ in 0..self.read_usize()? {
+                    array.push(Box::new(self.read_object()?));
+                }
+                Object::Array(array)
+            },
+            6 => {
+                let mut hash = HashMap::new();
+                for _ in 0..self.read_usize()? {
+                    let key = match self.read_object()?.hashable() {
+                        Some(key) => key,
+                        None => return Err(String::from("Unusable hash key in bytecode.")),
+                    };
+                    hash.insert(key, self.read_object()?);
+                }
+                Object::Hash(hash)
+            },
+            7 => Object::Builtin(self.read_str()?),
+            8 => {
+                let num_locals = self.read_usize()?;
+                let num_paras = self.read_usize()?;
+                Object::CompiledFunction {
+                    instructions: Rc::new(self.read_code()?),
+                    num_locals,
+                    num_paras,
+                }
+            },
+            tag => return Err(format!("Unknown object tag {}.", tag)),
+        };
+        Ok(obj)
+    }
+
+    fn read_code(&mut self) -> Result<Vec<Code>, String> {
+        let mut code = Vec::new();
+        for _ in 0..self.read_usize()? {
+            let c = match self.read_u8()? {
+                0 => Code::Constant(self.read_usize()?),
+                1 => Code::Pop,
+                2 => Code::Add,
+                3 => Code::Sub,
+                4 => Code::Mul,
+                5 => Code::Div,
+                6 => Code::Mod,
+                7 => Code::True,
+                8 => Code::False,
+                9 => Code::Equal,
+                10 => Code::NotEqual,
+                11 => Code::GreaterThan,
+                12 => Code::LessThan,
+                13 => Code::And,
+                14 => Code::Or,
+                15 => Code::Minus,
+                16 => Code::Bang,
+                17 => Code::JumpNotTruthy(self.read_usize()?),
+                18 => Code::Jump(self.read_usize()?),
+                19 => Code::Null,
+                20 => Code::SetGlobal(self.read_usize()?),
+                21 => Code::GetGlobal(self.read_usize()?),
+                22 => Code::Array(self.read_usize()?),
+                23 => Code::Hash(self.read_usize()?),
+                24 => Code::Index,
+                25 => Code::ReturnValue,
+                26 => Code::Return,
+                27 => Code::Call(self.read_usize()?),
+                28 => Code::SetLocal(self.read_usize()?),
+                29 => Code::GetLocal(self.read_usize()?),
+                30 => Code::CurrentFunction,
+                31 => Code::GetBuiltin(self.read_usize()?),
+                32 => Code::BitAnd,
+                33 => Code::BitOr,
+                34 => Code::BitXor,
+                35 => Code::ShiftLeft,
+                36 => Code::ShiftRight,
+                37 => Code::BitNot,
+                38 => Code::Dup,
+                39 => Code::Plus,
+                opcode => return Err(format!("Unknown opcode {}.", opcode)),
+            };
+            code.push(c);
+        }
+        Ok(code)
+    }
 }
 
 #[derive(PartialEq, Eq, Debug, Clone)]
 pub enum Scope {
     Global,
     Local,
+    Function,    // the function being compiled, so it can call itself
+    Builtin,
 }
 
 #[derive(PartialEq, Eq, Debug, Clone)]
@@ -54,11 +386,18 @@ pub struct SymbolTable {
 
 impl SymbolTable {
     pub fn new(outer: Option<Box<SymbolTable>>) -> SymbolTable {
-        SymbolTable {
+        let mut symbol_table = SymbolTable {
             outer,
             map: HashMap::new(),
             num_definitions: 0,
+        };
+        // Builtins live in the outermost scope, where globals can shadow them.
+        if symbol_table.outer.is_none() {
+            for (index, name) in BUILTINS.iter().enumerate() {
+                symbol_table.define_builtin(index, name);
+            }
         }
+        symbol_table
     }
 
     pub fn get_outer(mut self) -> Option<Box<SymbolTable>> {
@@ -80,13 +419,126 @@ impl SymbolTable {
         symbol
     }
 
+    pub fn define_function(&mut self, name: &str) -> Symbol {
+        let symbol = Symbol {
+            name: String::from(name),
+            scope: Scope::Function,
+            index: 0,
+        };
+        self.map.insert(String::from(name), symbol.clone());
+        symbol
+    }
+
+    pub fn define_builtin(&mut self, index: usize, name: &str) -> Symbol {
+        let symbol = Symbol {
+            name: String::from(name),
+            scope: Scope::Builtin,
+            index,
+        };
+        self.map.insert(String::from(name), symbol.clone());
+        symbol
+    }
+
     pub fn resolve(&self, name: &str) -> Option<Symbol> {
         if let Some(sym) = self.map.get(name) {
             Some(sym.clone())
         } else if let Some(outer) = &self.outer {
-            outer.resolve(name)
+            outer.resolve_outer(name)
         } else {
             None
         }
     }
+
+    fn resolve_outer(&self, name: &str) -> Option<Symbol> {
+        // The function of an enclosing scope is not the one being compiled, so skip it
+        // instead of letting a nested function get itself.
+        match self.map.get(name) {
+            Some(Symbol { scope: Scope::Function, .. }) | None => match &self.outer {
+                Some(outer) => outer.resolve_outer(name),
+                None => None,
+            },
+            Some(sym) => Some(sym.clone()),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::object::HashKey;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use crate::compiler::Compiler;
+
+    #[test]
+    fn disassemble_code() {
+        let constants = vec!(Object::Int(1), Object::Str(String::from("a")));
+        let code = vec!(
+            Code::Constant(0),
+            Code::Constant(1),
+            Code::JumpNotTruthy(5),
+            Code::GetGlobal(0),
+            Code::Jump(6),
+            Code::Null,
+            Code::Pop,
+        );
+        let output = "\
+0000 Constant 0 (1)
+0001 Constant 1 (\"a\")
+0002 JumpNotTruthy 0005
+0003 GetGlobal 0
+0004 Jump 0006
+0005 Null
+0006 Pop
+";
+        assert_eq!(disassemble(&code, &constants), output);
+
+        let input = "let f = fn(x) { if (x) { 1 } else { 2 } }; f(true);";
+        let lexer = Lexer::new(input);
+        let parser = Parser::new(lexer);
+        let (code, _, constants) = Compiler::new(parser, SymbolTable::new(None), vec!()).unwrap().run().unwrap();
+        let output = "\
+0000 Constant 2 (compiled function, locals 1, parameters 1)
+    0000 GetLocal 0
+    0001 JumpNotTruthy 0004
+    0002 Constant 0 (1)
+    0003 Jump 0005
+    0004 Constant 1 (2)
+    0005 ReturnValue
+0001 SetGlobal 0
+0002 GetGlobal 0
+0003 True
+0004 Call 1
+0005 Pop
+";
+        assert_eq!(disassemble(&code, &constants), output);
+    }
+
+    #[test]
+    fn serialize_code() {
+        let input = "
+            let fib = fn(n) { if (n < 2) { n } else { fib(n - 1) + fib(n - 2) } };
+            let a = [1, \"two\", 3.5, true];
+            let h = {\"k\": a[-1], 2: !false};
+            fib(-5 % 3) == 0 && h[\"k\"] || len(a) > 2;
+            1 & 2 | 3 ^ 4 << 5 >> ~6 + +7;
+        ";
+        let lexer = Lexer::new(input);
+        let parser = Parser::new(lexer);
+        let (code, _, mut constants) = Compiler::new(parser, SymbolTable::new(None), vec!()).unwrap().run().unwrap();
+        constants.push(Object::Array(vec!(Box::new(Object::Null), Box::new(Object::Builtin(String::from("len"))))));
+        constants.push(Object::Hash(HashMap::from([(HashKey::Int(1), Object::Bool(false))])));
+        let bytes = serialize(&code, &constants).unwrap();
+        assert_eq!(deserialize(&bytes), Ok((code, constants)));
+
+        let error = || Object::Error(String::from("x"));
+        assert_eq!(serialize(&[], &[error()]), Err(String::from("Cannot serialize Error(\"x\").")));
+        let nested = Object::Array(vec!(Box::new(Object::Int(1)), Box::new(Object::Return(Box::new(error())))));
+        assert_eq!(serialize(&[], &[nested]), Err(String::from("Cannot serialize Return(Error(\"x\")).")));
+
+        assert_eq!(deserialize(b"MBC"), Err(String::from("Invalid bytecode header.")));
+        assert_eq!(deserialize(&bytes[..bytes.len()-1]), Err(String::from("Unexpected end of bytecode.")));
+    }
 }
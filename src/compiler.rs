@@ -1,36 +1,117 @@
 use crate::code::Code;
-use crate::lexer::Lexer;
 use crate::parser::Parser;
+use crate::parser::ParseError;
 use crate::ast::Statement;
 use crate::ast::Expression;
 use crate::object::Object;
+use crate::object::HashKey;
 use crate::code::SymbolTable;
 use crate::code::Symbol;
 use crate::code::Scope;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct CompileError {
+    pub message: String,
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Compile error: {}", self.message)
+    }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct Bytecode {
+    pub instructions: Vec<Code>,
+    pub constants: Vec<Object>,
+}
 
 pub struct Compiler {
     input: Option<Vec<Statement>>,
     scopes: Vec<Vec<Code>>,    // Vec<instructions>
     instructions: Vec<Code>,
     symbol_table: SymbolTable,
+    constants: Vec<Object>,
+    constant_indexes: HashMap<HashKey, usize>,    // where each hashable constant is in the pool
 }
 
 impl Compiler {
-    pub fn new(parser: Parser, symbol_table: SymbolTable) -> Compiler {
-        Compiler {
-            input: Some(parser.collect()),
+    pub fn new(parser: Parser, symbol_table: SymbolTable,
+               constants: Vec<Object>) -> Result<Compiler, Vec<ParseError>> {
+        let (input, errors) = parser.parse_program();
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        let mut constant_indexes = HashMap::new();
+        for (index, obj) in constants.iter().enumerate() {
+            if let Some(key) = obj.hashable() {
+                constant_indexes.entry(key).or_insert(index);
+            }
+        }
+        Ok(Compiler {
+            input: Some(input),
             scopes: vec!(),
             instructions: vec!(),
             symbol_table,
-        }
+            constants,
+            constant_indexes,
+        })
     }
 
-    pub fn run(mut self) -> (Vec<Code>, SymbolTable) {
+    pub fn run(mut self) -> Result<(Vec<Code>, SymbolTable, Vec<Object>), CompileError> {
         let input = self.input.take().unwrap();
         for stmt in input.into_iter() {
-            self.compile_statement(stmt);
+            self.compile_statement(stmt)?;
         }
-        (self.instructions, self.symbol_table)
+        Ok((self.instructions, self.symbol_table, self.constants))
+    }
+
+    // Compile the program on its own, without the symbol table for the next input.
+    pub fn compile_program(self) -> Result<Bytecode, CompileError> {
+        let (instructions, _symbol_table, constants) = self.run()?;
+        Ok(Bytecode { instructions, constants })
+    }
+
+    fn add_constant(&mut self, obj: Object) -> usize {
+        // Equal ints, strings and bools share one slot in the pool. Floats and functions
+        // always get a new one, rather than being compared with the whole pool.
+        let key = obj.hashable();
+        if let Some(index) = key.as_ref().and_then(|key| self.constant_indexes.get(key)) {
+            return *index;
+        }
+        self.constants.push(obj);
+        let index = self.constants.len() - 1;
+        if let Some(key) = key {
+            self.constant_indexes.insert(key, index);
+        }
+        index
+    }
+
+    fn emit(&mut self, code: Code) -> usize {
+        // Push the instruction and return its position, so it can be patched later.
+        self.instructions.push(code);
+        self.instructions.len() - 1
+    }
+
+    fn patch_jump(&mut self, pos: usize, target: usize) {
+        // Overwrite the placeholder target of the jump at pos.
+        self.instructions[pos] = match self.instructions[pos] {
+            Code::JumpNotTruthy(_) => Code::JumpNotTruthy(target),
+            Code::Jump(_) => Code::Jump(target),
+            ref code => panic!("Expect a jump at {}, get {}.", pos, code),
+        };
+    }
+
+    fn emit_constant(&mut self, obj: Object) {
+        let index = self.add_constant(obj);
+        self.instructions.push(Code::Constant(index));
+    }
+
+    fn emit_dup(&mut self) {
+        self.instructions.push(Code::Dup);
     }
 
     fn enter_scope(&mut self) {
@@ -48,150 +129,286 @@ impl Compiler {
         (instructions, num_locals)
     }
 
-    fn compile_statement(&mut self, stmt: Statement) {
+    fn compile_statement(&mut self, stmt: Statement) -> Result<(), CompileError> {
         match stmt {
-            Statement::Let { ident, expr } => self.compile_let(ident, expr),
+            Statement::Let { ident, expr } => self.compile_let(ident, expr)?,
+            Statement::Assign { ident, expr } => self.compile_assign(ident, expr)?,
             Statement::Return(expr) => {
-                self.compile_expression(expr);
+                self.compile_expression(expr)?;
                 self.instructions.push(Code::ReturnValue);
             },
             Statement::Expr(expr) => {
-                self.compile_expression(expr);
+                self.compile_expression(expr)?;
                 self.instructions.push(Code::Pop);
             },
+            Statement::While { condition, body } => self.compile_while(condition, *body)?,
+            Statement::DoWhile { body, condition } => self.compile_do_while(*body, condition)?,
+            Statement::For { .. } => return Err(error(String::from("For loops are not supported by the compiler yet."))),
             Statement::Block(block) => {
                 for stmt in block.iter() {
-                    self.compile_statement((**stmt).clone());
+                    self.compile_statement((**stmt).clone())?;
                 }
             },
-        }
+        };
+        Ok(())
     }
 
-    fn compile_let(&mut self, ident: Expression, expr: Expression) {
-        self.compile_expression(expr);
+    fn compile_let(&mut self, ident: Expression, expr: Expression) -> Result<(), CompileError> {
         let name = match ident {
             Expression::Ident(name) => name,
-            ident => panic!("Invalid identifier {:?}.", ident),
+            ident => return Err(error(format!("Invalid identifier {:?}.", ident))),
+        };
+        let symbol = match expr {
+            Expression::Function { parameters, body } if self.scopes.is_empty() => {
+                // Define a global function first, so the functions nested in it can call it
+                // through the global instead of getting themselves.
+                let symbol = self.symbol_table.define(&name);
+                self.compile_function(parameters, *body, Some(&name))?;
+                symbol
+            },
+            Expression::Function { parameters, body } => {
+                self.compile_function(parameters, *body, Some(&name))?;
+                self.symbol_table.define(&name)
+            },
+            expr => {
+                self.compile_expression(expr)?;
+                self.symbol_table.define(&name)
+            },
         };
-        let symbol = self.symbol_table.define(&name);
         match symbol.scope {
             Scope::Global => self.instructions.push(Code::SetGlobal(symbol.index)),
             Scope::Local => self.instructions.push(Code::SetLocal(symbol.index)),
+            scope => return Err(error(format!("Unexpected {:?} symbol {}.", scope, name))),
+        };
+        Ok(())
+    }
+
+    fn compile_assign(&mut self, ident: Expression, expr: Expression) -> Result<(), CompileError> {
+        self.compile_expression(expr)?;
+        let name = match ident {
+            Expression::Ident(name) => name,
+            Expression::Infix { .. } => {
+                return Err(error(String::from("Index assignments are not supported by the compiler yet.")));
+            },
+            ident => return Err(error(format!("Invalid identifier {:?}.", ident))),
+        };
+        match self.symbol_table.resolve(&name) {
+            Some(Symbol { name: _, scope: Scope::Global, index }) => self.instructions.push(Code::SetGlobal(index)),
+            Some(Symbol { name: _, scope: Scope::Local, index }) => self.instructions.push(Code::SetLocal(index)),
+            Some(Symbol { name: _, scope: Scope::Function, index: _ }) => {
+                return Err(error(format!("Cannot reassign function {}.", name)));
+            },
+            Some(Symbol { name: _, scope: Scope::Builtin, index: _ }) => {
+                return Err(error(format!("Cannot reassign builtin {}.", name)));
+            },
+            None => return Err(error(format!("Identifier {} not found.", name))),
         };
+        Ok(())
     }
 
-    fn compile_expression(&mut self, expr: Expression) {
+    fn compile_expression(&mut self, expr: Expression) -> Result<(), CompileError> {
         match expr {
-            Expression::Ident(v) => self.compile_ident(v),
-            Expression::Int(v) => self.compile_int(v),
-            Expression::Str(v) => self.instructions.push(Code::Constant(Object::Str(v))),
-            Expression::Bool(v) => self.compile_bool(v),
-            Expression::Array(exprs) => self.compile_array(exprs),
-            Expression::Prefix { operator, expr } => self.compile_prefix(operator, *expr),
-            Expression::Infix { operator, left, right } => self.compile_infix(operator, *left, *right),
-            Expression::If { condition, consequence, alternative } => self.compile_if(*condition, *consequence, *alternative),
-            Expression::Function { parameters, body } => self.compile_function(parameters, *body),
-            Expression::Call { function, arguments } => self.compile_call(*function, arguments),
-        }
+            Expression::Ident(v) => self.compile_ident(v)?,
+            Expression::Int(v) => self.compile_int(v)?,
+            Expression::Float(v) => self.emit_constant(Object::Float(v.parse().unwrap())),
+            Expression::Str(v) => self.emit_constant(Object::Str(v)),
+            Expression::Bool(v) => self.compile_bool(v)?,
+            Expression::Null => self.instructions.push(Code::Null),
+            Expression::Array(exprs) => self.compile_array(exprs)?,
+            Expression::Hash(pairs) => self.compile_hash(pairs)?,
+            Expression::Prefix { operator, expr } => self.compile_prefix(operator, *expr)?,
+            Expression::Infix { operator, left, right } => self.compile_infix(operator, *left, *right)?,
+            Expression::If { condition, consequence, alternative } => {
+                self.compile_if(*condition, *consequence, *alternative)?
+            },
+            Expression::Function { parameters, body } => self.compile_function(parameters, *body, None)?,
+            Expression::Call { function, arguments } => self.compile_call(*function, arguments)?,
+            Expression::Block(block) => {
+                // Bindings made in a block are local to it, but keep their slots.
+                let map = self.symbol_table.map.clone();
+                self.compile_branch(*block)?;
+                self.symbol_table.map = map;
+            },
+            Expression::Slice { .. } => return Err(error(String::from("Slices are not supported by the compiler yet."))),
+        };
+        Ok(())
     }
 
-    fn compile_ident(&mut self, v: String) {
+    fn compile_ident(&mut self, v: String) -> Result<(), CompileError> {
         match self.symbol_table.resolve(&v) {
             Some(Symbol { name: _, scope: Scope::Global, index }) => self.instructions.push(Code::GetGlobal(index)),
             Some(Symbol { name: _, scope: Scope::Local, index }) => self.instructions.push(Code::GetLocal(index)),
-            None => panic!("Identifier {} not found.", v),
+            Some(Symbol { name: _, scope: Scope::Function, index: _ }) => self.instructions.push(Code::CurrentFunction),
+            Some(Symbol { name: _, scope: Scope::Builtin, index }) => self.instructions.push(Code::GetBuiltin(index)),
+            None => return Err(error(format!("Identifier {} not found.", v))),
         };
+        Ok(())
     }
 
-    fn compile_int(&mut self, v: String) {
-        let int = Object::Int(i32::from_str_radix(&v, 10).unwrap());
-        self.instructions.push(Code::Constant(int));
+    fn compile_int(&mut self, v: String) -> Result<(), CompileError> {
+        match v.parse() {
+            Ok(v) => self.emit_constant(Object::Int(v)),
+            Err(_) => return Err(error(String::from("Integer overflow."))),
+        };
+        Ok(())
     }
 
-    fn compile_bool(&mut self, v: String) {
+    fn compile_bool(&mut self, v: String) -> Result<(), CompileError> {
         match v.as_str() {
             "true" => self.instructions.push(Code::True),
             "false" => self.instructions.push(Code::False),
-            v => panic!("Invalid bool {}.", v),
-        }
+            v => return Err(error(format!("Invalid bool {}.", v))),
+        };
+        Ok(())
     }
 
-    fn compile_array(&mut self, exprs: Vec<Box<Expression>>) {
+    fn compile_array(&mut self, exprs: Vec<Box<Expression>>) -> Result<(), CompileError> {
         let size = exprs.len();
         for expr in exprs.into_iter() {
-            self.compile_expression(*expr);
+            self.compile_expression(*expr)?;
         }
         self.instructions.push(Code::Array(size));
+        Ok(())
+    }
+
+    fn compile_hash(&mut self, pairs: Vec<(Box<Expression>, Box<Expression>)>) -> Result<(), CompileError> {
+        let size = pairs.len();
+        for (key, value) in pairs.into_iter() {
+            self.compile_expression(*key)?;
+            self.compile_expression(*value)?;
+        }
+        self.instructions.push(Code::Hash(size));
+        Ok(())
     }
 
-    fn compile_prefix(&mut self, operator: String, expr: Expression) {
-        self.compile_expression(expr);
+    fn compile_prefix(&mut self, operator: String, expr: Expression) -> Result<(), CompileError> {
+        if operator == "-" {
+            if let Some(v) = fold_negation(&expr) {
+                self.emit_constant(Object::Int(v));
+                return Ok(());
+            }
+        }
+        self.compile_expression(expr)?;
         match operator.as_str() {
             "-" => self.instructions.push(Code::Minus),
             "!" => self.instructions.push(Code::Bang),
-            op => panic!("Unknown operator {}.", op),
+            "~" => self.instructions.push(Code::BitNot),
+            "+" => self.instructions.push(Code::Plus),
+            op => return Err(error(format!("Unknown operator {}.", op))),
         };
+        Ok(())
     }
 
-    fn compile_infix(&mut self, operator: String, left: Expression, right: Expression) {
-        self.compile_expression(left);
-        self.compile_expression(right);
+    fn compile_infix(&mut self, operator: String, left: Expression, right: Expression) -> Result<(), CompileError> {
+        if operator == "&&" || operator == "||" {
+            return self.compile_logical(operator, left, right);
+        }
+        self.compile_expression(left)?;
+        self.compile_expression(right)?;
         match operator.as_str() {
             "+" => self.instructions.push(Code::Add),
             "-" => self.instructions.push(Code::Sub),
             "*" => self.instructions.push(Code::Mul),
             "/" => self.instructions.push(Code::Div),
+            "%" => self.instructions.push(Code::Mod),
             "==" => self.instructions.push(Code::Equal),
             "!=" => self.instructions.push(Code::NotEqual),
             ">" => self.instructions.push(Code::GreaterThan),
             "<" => self.instructions.push(Code::LessThan),
+            "&" => self.instructions.push(Code::BitAnd),
+            "|" => self.instructions.push(Code::BitOr),
+            "^" => self.instructions.push(Code::BitXor),
+            "<<" => self.instructions.push(Code::ShiftLeft),
+            ">>" => self.instructions.push(Code::ShiftRight),
             "[" => self.instructions.push(Code::Index),
-            op => panic!("Unknown operator {}.", op),
+            op => return Err(error(format!("Unknown operator {}.", op))),
         };
+        Ok(())
     }
 
-    fn compile_if(&mut self, condition: Expression,
-                  consequence: Statement, alternative: Statement) {
-        self.compile_expression(condition);
-        // consequence
-        let pos = self.instructions.len();
-        self.instructions.push(Code::JumpNotTruthy(9999));
-        self.compile_statement(consequence);
-        match self.instructions.pop().unwrap() {
-            Code::Pop => (),
-            code => self.instructions.push(code),
-        }
-        let offset = self.instructions.len() - pos;
-        self.instructions.push(Code::JumpNotTruthy(offset));
-        self.instructions.swap_remove(pos);
-        // alternative
-        let pos = self.instructions.len();
-        self.instructions.push(Code::Jump(9999));
-        self.compile_statement(alternative);
-        match self.instructions.pop().unwrap() {
-            Code::Pop => (),
-            code => self.instructions.push(code),
+    fn compile_logical(&mut self, operator: String, left: Expression, right: Expression) -> Result<(), CompileError> {
+        // Skip the right operand once the left one decides the result, like the evaluator.
+        // The left operand is still combined with a constant, so a non-bool one is an error.
+        let (code, constant) = if operator == "&&" { (Code::And, Code::False) } else { (Code::Or, Code::True) };
+        self.compile_expression(left)?;
+        self.emit_dup();
+        if code == Code::Or {
+            self.emit(Code::Bang);
         }
-        let mut offset = self.instructions.len() - 1 - pos;
-        if offset == 0 {
-            offset = 1;
+        let jump_short = self.emit(Code::JumpNotTruthy(0));
+        self.compile_expression(right)?;
+        self.emit(code.clone());
+        let jump_end = self.emit(Code::Jump(0));
+        self.patch_jump(jump_short, self.instructions.len());
+        self.emit(constant);
+        self.emit(code);
+        self.patch_jump(jump_end, self.instructions.len());
+        Ok(())
+    }
+
+    fn compile_if(&mut self, condition: Expression,
+                  consequence: Statement, alternative: Statement) -> Result<(), CompileError> {
+        self.compile_expression(condition)?;
+        // Emit the jumps with placeholder targets, and back-patch them once the branches are compiled.
+        let jump_not_truthy = self.emit(Code::JumpNotTruthy(0));
+        self.compile_branch(consequence)?;
+        let jump = self.emit(Code::Jump(0));
+        self.patch_jump(jump_not_truthy, self.instructions.len());
+        self.compile_branch(alternative)?;
+        self.patch_jump(jump, self.instructions.len());
+        Ok(())
+    }
+
+    fn compile_while(&mut self, condition: Expression, body: Statement) -> Result<(), CompileError> {
+        // Jump back to the condition after the body, and out of the loop once it is falsy.
+        let start = self.instructions.len();
+        self.compile_expression(condition)?;
+        let jump_not_truthy = self.emit(Code::JumpNotTruthy(0));
+        self.compile_statement(body)?;
+        self.emit(Code::Jump(start));
+        self.patch_jump(jump_not_truthy, self.instructions.len());
+        Ok(())
+    }
+
+    fn compile_do_while(&mut self, body: Statement, condition: Expression) -> Result<(), CompileError> {
+        // Check the condition after the body, jumping back to the body while it is truthy.
+        let start = self.instructions.len();
+        self.compile_statement(body)?;
+        self.compile_expression(condition)?;
+        let jump_not_truthy = self.emit(Code::JumpNotTruthy(0));
+        self.emit(Code::Jump(start));
+        self.patch_jump(jump_not_truthy, self.instructions.len());
+        Ok(())
+    }
+
+    fn compile_branch(&mut self, block: Statement) -> Result<(), CompileError> {
+        // Leave the value of the branch on the stack, or Null if the branch is empty
+        // or does not end with an expression.
+        let start = self.instructions.len();
+        self.compile_statement(block)?;
+        if self.instructions.len() > start && self.instructions.last() == Some(&Code::Pop) {
+            self.instructions.pop();
+        } else {
             self.instructions.push(Code::Null);
-        };
-        self.instructions.push(Code::Jump(offset));
-        self.instructions.swap_remove(pos);
+        }
+        Ok(())
     }
 
-    fn compile_function(&mut self, parameters: Vec<Box<Expression>>, body: Statement) {
+    fn compile_function(&mut self, parameters: Vec<Box<Expression>>, body: Statement,
+                        name: Option<&str>) -> Result<(), CompileError> {
         self.enter_scope();
+        if let Some(name) = name {
+            self.symbol_table.define_function(name);
+        }
         let num_paras = parameters.len();
         for para in parameters.into_iter() {
             let name = match *para {
                 Expression::Ident(name) => name,
-                expr => panic!("Expect Expression::Ident, get {:?}.", expr),
+                expr => return Err(error(format!("Expect Expression::Ident, get {:?}.", expr))),
             };
             self.symbol_table.define(&name);
         }
-        self.compile_statement(body);
+        self.compile_statement(body)?;
         let (mut instructions, num_locals) = self.leave_scope();
         match instructions.pop() {
             Some(Code::Pop) => instructions.push(Code::ReturnValue),
@@ -199,20 +416,37 @@ impl Compiler {
             Some(code) => instructions.push(code),
         };
         let compiled_function = Object::CompiledFunction {
-            instructions,
+            instructions: Rc::new(instructions),
             num_locals,
             num_paras,
         };
-        self.instructions.push(Code::Constant(compiled_function));
+        self.emit_constant(compiled_function);
+        Ok(())
     }
 
-    fn compile_call(&mut self, function: Expression, arguments: Vec<Box<Expression>>) {
-        self.compile_expression(function);
+    fn compile_call(&mut self, function: Expression, arguments: Vec<Box<Expression>>) -> Result<(), CompileError> {
+        self.compile_expression(function)?;
         let num_args = arguments.len();
         for arg in arguments.into_iter() {
-            self.compile_expression(*arg);
+            self.compile_expression(*arg)?;
         }
         self.instructions.push(Code::Call(num_args));
+        Ok(())
+    }
+}
+
+fn error(message: String) -> CompileError {
+    CompileError { message }
+}
+
+// Fold a minus applied to an integer literal, possibly negated again, into one constant.
+// An overflowing value is not folded, so the VM still reports it at runtime.
+fn fold_negation(expr: &Expression) -> Option<i64> {
+    match expr {
+        // Parse with the sign, so the smallest integer does not overflow on the way.
+        Expression::Int(v) => format!("-{}", v).parse().ok(),
+        Expression::Prefix { operator, expr } if operator == "-" => fold_negation(expr)?.checked_neg(),
+        _ => None,
     }
 }
 
@@ -221,97 +455,229 @@ impl Compiler {
 mod tests {
 
     use super::*;
+    use crate::lexer::Lexer;
 
     #[test]
     fn compiler() {
         let test_array = [
             ("1 + 2;", vec!(
-                Code::Constant(Object::Int(1)),
-                Code::Constant(Object::Int(2)),
+                Object::Int(1),
+                Object::Int(2),
+            ), vec!(
+                Code::Constant(0),
+                Code::Constant(1),
+                Code::Add,
+                Code::Pop,
+            )),
+            ("1 + 1 + 1;", vec!(
+                Object::Int(1),
+            ), vec!(
+                Code::Constant(0),
+                Code::Constant(0),
+                Code::Add,
+                Code::Constant(0),
+                Code::Add,
+                Code::Pop,
+            )),
+            ("1.5 + 1;", vec!(
+                Object::Float(1.5),
+                Object::Int(1),
+            ), vec!(
+                Code::Constant(0),
+                Code::Constant(1),
                 Code::Add,
                 Code::Pop,
             )),
             ("1 - 2;", vec!(
-                Code::Constant(Object::Int(1)),
-                Code::Constant(Object::Int(2)),
+                Object::Int(1),
+                Object::Int(2),
+            ), vec!(
+                Code::Constant(0),
+                Code::Constant(1),
                 Code::Sub,
                 Code::Pop,
             )),
             ("1 * 2;", vec!(
-                Code::Constant(Object::Int(1)),
-                Code::Constant(Object::Int(2)),
+                Object::Int(1),
+                Object::Int(2),
+            ), vec!(
+                Code::Constant(0),
+                Code::Constant(1),
                 Code::Mul,
                 Code::Pop,
             )),
             ("1 / 2;", vec!(
-                Code::Constant(Object::Int(1)),
-                Code::Constant(Object::Int(2)),
+                Object::Int(1),
+                Object::Int(2),
+            ), vec!(
+                Code::Constant(0),
+                Code::Constant(1),
                 Code::Div,
                 Code::Pop,
             )),
+            ("5 % 3;", vec!(
+                Object::Int(5),
+                Object::Int(3),
+            ), vec!(
+                Code::Constant(0),
+                Code::Constant(1),
+                Code::Mod,
+                Code::Pop,
+            )),
             ("1 == 2;", vec!(
-                Code::Constant(Object::Int(1)),
-                Code::Constant(Object::Int(2)),
+                Object::Int(1),
+                Object::Int(2),
+            ), vec!(
+                Code::Constant(0),
+                Code::Constant(1),
                 Code::Equal,
                 Code::Pop,
             )),
             ("1 != 2;", vec!(
-                Code::Constant(Object::Int(1)),
-                Code::Constant(Object::Int(2)),
+                Object::Int(1),
+                Object::Int(2),
+            ), vec!(
+                Code::Constant(0),
+                Code::Constant(1),
                 Code::NotEqual,
                 Code::Pop,
             )),
             ("1 > 2;", vec!(
-                Code::Constant(Object::Int(1)),
-                Code::Constant(Object::Int(2)),
+                Object::Int(1),
+                Object::Int(2),
+            ), vec!(
+                Code::Constant(0),
+                Code::Constant(1),
                 Code::GreaterThan,
                 Code::Pop,
             )),
             ("1 < 2;", vec!(
-                Code::Constant(Object::Int(1)),
-                Code::Constant(Object::Int(2)),
+                Object::Int(1),
+                Object::Int(2),
+            ), vec!(
+                Code::Constant(0),
+                Code::Constant(1),
                 Code::LessThan,
                 Code::Pop,
             )),
+            ("true && false || true;", vec!(), vec!(
+                Code::True,
+                Code::Dup,
+                Code::JumpNotTruthy(6),
+                Code::False,
+                Code::And,
+                Code::Jump(8),
+                Code::False,
+                Code::And,
+                Code::Dup,
+                Code::Bang,
+                Code::JumpNotTruthy(14),
+                Code::True,
+                Code::Or,
+                Code::Jump(16),
+                Code::True,
+                Code::Or,
+                Code::Pop,
+            )),
             ("-1;", vec!(
-                Code::Constant(Object::Int(1)),
+                Object::Int(-1),
+            ), vec!(
+                Code::Constant(0),
+                Code::Pop,
+            )),
+            ("-(-3);", vec!(
+                Object::Int(3),
+            ), vec!(
+                Code::Constant(0),
+                Code::Pop,
+            )),
+            ("-9223372036854775808;", vec!(
+                Object::Int(i64::MIN),
+            ), vec!(
+                Code::Constant(0),
+                Code::Pop,
+            )),
+            ("-(-9223372036854775808);", vec!(
+                Object::Int(i64::MIN),
+            ), vec!(
+                Code::Constant(0),
+                Code::Minus,
+                Code::Pop,
+            )),
+            ("-1.5;", vec!(
+                Object::Float(1.5),
+            ), vec!(
+                Code::Constant(0),
                 Code::Minus,
                 Code::Pop,
             )),
-            ("!true;", vec!(
+            ("!true;", vec!(), vec!(
                 Code::True,
                 Code::Bang,
                 Code::Pop,
             )),
             ("if (true) { 1 } else {2};", vec!(
+                Object::Int(1),
+                Object::Int(2),
+            ), vec!(
                 Code::True,
-                Code::JumpNotTruthy(2),
-                Code::Constant(Object::Int(1)),
-                Code::Jump(1),
-                Code::Constant(Object::Int(2)),
+                Code::JumpNotTruthy(4),
+                Code::Constant(0),
+                Code::Jump(5),
+                Code::Constant(1),
                 Code::Pop,
             )),
             ("if (true) { 1 };", vec!(
+                Object::Int(1),
+            ), vec!(
                 Code::True,
-                Code::JumpNotTruthy(2),
-                Code::Constant(Object::Int(1)),
-                Code::Jump(1),
+                Code::JumpNotTruthy(4),
+                Code::Constant(0),
+                Code::Jump(5),
+                Code::Null,
+                Code::Pop,
+            )),
+            ("if (true) {};", vec!(), vec!(
+                Code::True,
+                Code::JumpNotTruthy(4),
+                Code::Null,
+                Code::Jump(5),
                 Code::Null,
                 Code::Pop,
             )),
             ("if (false) { 1 };", vec!(
+                Object::Int(1),
+            ), vec!(
                 Code::False,
-                Code::JumpNotTruthy(2),
-                Code::Constant(Object::Int(1)),
-                Code::Jump(1),
+                Code::JumpNotTruthy(4),
+                Code::Constant(0),
+                Code::Jump(5),
                 Code::Null,
                 Code::Pop,
             )),
+            ("if (true) { if (false) { 1 } else { 2 } } else { 3 };", vec!(
+                Object::Int(1),
+                Object::Int(2),
+                Object::Int(3),
+            ), vec!(
+                Code::True,
+                Code::JumpNotTruthy(8),
+                Code::False,
+                Code::JumpNotTruthy(6),
+                Code::Constant(0),
+                Code::Jump(7),
+                Code::Constant(1),
+                Code::Jump(9),
+                Code::Constant(2),
+                Code::Pop,
+            )),
             ("!(if (false) { 1 });", vec!(
+                Object::Int(1),
+            ), vec!(
                 Code::False,
-                Code::JumpNotTruthy(2),
-                Code::Constant(Object::Int(1)),
-                Code::Jump(1),
+                Code::JumpNotTruthy(4),
+                Code::Constant(0),
+                Code::Jump(5),
                 Code::Null,
                 Code::Bang,
                 Code::Pop,
@@ -323,106 +689,356 @@ mod tests {
                     y;
                 }
             ", vec!(
-                Code::Constant(Object::Int(5)),
+                Object::Int(5),
+                Object::Int(1),
+            ), vec!(
+                Code::Constant(0),
                 Code::SetGlobal(0),
                 Code::GetGlobal(0),
-                Code::Constant(Object::Int(1)),
+                Code::Constant(1),
                 Code::GreaterThan,
-                Code::JumpNotTruthy(6),
+                Code::JumpNotTruthy(12),
                 Code::GetGlobal(0),
-                Code::Constant(Object::Int(1)),
+                Code::Constant(1),
                 Code::Add,
                 Code::SetGlobal(1),
                 Code::GetGlobal(1),
-                Code::Jump(1),
+                Code::Jump(13),
                 Code::Null,
                 Code::Pop,
             )),
+            ("let i = 0; while (i < 3) { i = i + 1; }", vec!(
+                Object::Int(0),
+                Object::Int(3),
+                Object::Int(1),
+            ), vec!(
+                Code::Constant(0),
+                Code::SetGlobal(0),
+                Code::GetGlobal(0),
+                Code::Constant(1),
+                Code::LessThan,
+                Code::JumpNotTruthy(11),
+                Code::GetGlobal(0),
+                Code::Constant(2),
+                Code::Add,
+                Code::SetGlobal(0),
+                Code::Jump(2),
+            )),
+            ("let i = 0; do { i = i + 1; } while (false);", vec!(
+                Object::Int(0),
+                Object::Int(1),
+            ), vec!(
+                Code::Constant(0),
+                Code::SetGlobal(0),
+                Code::GetGlobal(0),
+                Code::Constant(1),
+                Code::Add,
+                Code::SetGlobal(0),
+                Code::False,
+                Code::JumpNotTruthy(9),
+                Code::Jump(2),
+            )),
+            ("let x = 1; x = 2;", vec!(
+                Object::Int(1),
+                Object::Int(2),
+            ), vec!(
+                Code::Constant(0),
+                Code::SetGlobal(0),
+                Code::Constant(1),
+                Code::SetGlobal(0),
+            )),
             ("\"a\" + \"b\";", vec!(
-                Code::Constant(Object::Str(String::from("a"))),
-                Code::Constant(Object::Str(String::from("b"))),
+                Object::Str(String::from("a")),
+                Object::Str(String::from("b")),
+            ), vec!(
+                Code::Constant(0),
+                Code::Constant(1),
                 Code::Add,
                 Code::Pop,
             )),
             ("[1, 2][1];", vec!(
-                Code::Constant(Object::Int(1)),
-                Code::Constant(Object::Int(2)),
+                Object::Int(1),
+                Object::Int(2),
+            ), vec!(
+                Code::Constant(0),
+                Code::Constant(1),
                 Code::Array(2),
-                Code::Constant(Object::Int(1)),
+                Code::Constant(0),
                 Code::Index,
                 Code::Pop,
             )),
+            ("{1: 2, \"a\": 3 + 4};", vec!(
+                Object::Int(1),
+                Object::Int(2),
+                Object::Str(String::from("a")),
+                Object::Int(3),
+                Object::Int(4),
+            ), vec!(
+                Code::Constant(0),
+                Code::Constant(1),
+                Code::Constant(2),
+                Code::Constant(3),
+                Code::Constant(4),
+                Code::Add,
+                Code::Hash(2),
+                Code::Pop,
+            )),
             ("fn() { return 1; }();", vec!(
-                Code::Constant(Object::CompiledFunction {
-                    instructions: vec!(
-                        Code::Constant(Object::Int(1)),
+                Object::Int(1),
+                Object::CompiledFunction {
+                    instructions: Rc::new(vec!(
+                        Code::Constant(0),
                         Code::ReturnValue,
-                    ),
+                    )),
                     num_locals: 0,
                     num_paras: 0,
-                }),
+                },
+            ), vec!(
+                Code::Constant(1),
                 Code::Call(0),
                 Code::Pop,
             )),
             ("fn() { 1; }();", vec!(
-                Code::Constant(Object::CompiledFunction {
-                    instructions: vec!(
-                        Code::Constant(Object::Int(1)),
+                Object::Int(1),
+                Object::CompiledFunction {
+                    instructions: Rc::new(vec!(
+                        Code::Constant(0),
                         Code::ReturnValue,
-                    ),
+                    )),
                     num_locals: 0,
                     num_paras: 0,
-                }),
+                },
+            ), vec!(
+                Code::Constant(1),
                 Code::Call(0),
                 Code::Pop,
             )),
             ("fn() {}();", vec!(
-                Code::Constant(Object::CompiledFunction {
-                    instructions: vec!(
+                Object::CompiledFunction {
+                    instructions: Rc::new(vec!(
                         Code::Return,
-                    ),
+                    )),
                     num_locals: 0,
                     num_paras: 0,
-                }),
+                },
+            ), vec!(
+                Code::Constant(0),
                 Code::Call(0),
                 Code::Pop,
             )),
             ("fn() { let a = 1; a; }();", vec!(
-                Code::Constant(Object::CompiledFunction {
-                    instructions: vec!(
-                        Code::Constant(Object::Int(1)),
+                Object::Int(1),
+                Object::CompiledFunction {
+                    instructions: Rc::new(vec!(
+                        Code::Constant(0),
                         Code::SetLocal(0),
                         Code::GetLocal(0),
                         Code::ReturnValue,
-                    ),
+                    )),
                     num_locals: 1,
                     num_paras: 0,
-                }),
+                },
+            ), vec!(
+                Code::Constant(1),
                 Code::Call(0),
                 Code::Pop,
             )),
             ("fn(a) { a; }(1);", vec!(
-                Code::Constant(Object::CompiledFunction {
-                    instructions: vec!(
+                Object::CompiledFunction {
+                    instructions: Rc::new(vec!(
+                        Code::GetLocal(0),
+                        Code::ReturnValue,
+                    )),
+                    num_locals: 1,
+                    num_paras: 1,
+                },
+                Object::Int(1),
+            ), vec!(
+                Code::Constant(0),
+                Code::Constant(1),
+                Code::Call(1),
+                Code::Pop,
+            )),
+            ("null;", vec!(), vec!(
+                Code::Null,
+                Code::Pop,
+            )),
+            ("~1;", vec!(
+                Object::Int(1),
+            ), vec!(
+                Code::Constant(0),
+                Code::BitNot,
+                Code::Pop,
+            )),
+            ("+1;", vec!(
+                Object::Int(1),
+            ), vec!(
+                Code::Constant(0),
+                Code::Plus,
+                Code::Pop,
+            )),
+            ("1 & 2; 1 | 2; 1 ^ 2; 1 << 2; 1 >> 2;", vec!(
+                Object::Int(1),
+                Object::Int(2),
+            ), vec!(
+                Code::Constant(0),
+                Code::Constant(1),
+                Code::BitAnd,
+                Code::Pop,
+                Code::Constant(0),
+                Code::Constant(1),
+                Code::BitOr,
+                Code::Pop,
+                Code::Constant(0),
+                Code::Constant(1),
+                Code::BitXor,
+                Code::Pop,
+                Code::Constant(0),
+                Code::Constant(1),
+                Code::ShiftLeft,
+                Code::Pop,
+                Code::Constant(0),
+                Code::Constant(1),
+                Code::ShiftRight,
+                Code::Pop,
+            )),
+            ("len([1, 2, 3]);", vec!(
+                Object::Int(1),
+                Object::Int(2),
+                Object::Int(3),
+            ), vec!(
+                Code::GetBuiltin(0),
+                Code::Constant(0),
+                Code::Constant(1),
+                Code::Constant(2),
+                Code::Array(3),
+                Code::Call(1),
+                Code::Pop,
+            )),
+            ("let len = 1; len;", vec!(
+                Object::Int(1),
+            ), vec!(
+                Code::Constant(0),
+                Code::SetGlobal(0),
+                Code::GetGlobal(0),
+                Code::Pop,
+            )),
+            ("let fib = fn(n) { if (n < 2) { n } else { fib(n - 1) + fib(n - 2) } }; fib(6);", vec!(
+                Object::Int(2),
+                Object::Int(1),
+                Object::CompiledFunction {
+                    instructions: Rc::new(vec!(
+                        Code::GetLocal(0),
+                        Code::Constant(0),
+                        Code::LessThan,
+                        Code::JumpNotTruthy(6),
+                        Code::GetLocal(0),
+                        Code::Jump(17),
+                        Code::CurrentFunction,
+                        Code::GetLocal(0),
+                        Code::Constant(1),
+                        Code::Sub,
+                        Code::Call(1),
+                        Code::CurrentFunction,
                         Code::GetLocal(0),
+                        Code::Constant(0),
+                        Code::Sub,
+                        Code::Call(1),
+                        Code::Add,
                         Code::ReturnValue,
-                    ),
+                    )),
                     num_locals: 1,
                     num_paras: 1,
-                }),
-                Code::Constant(Object::Int(1)),
+                },
+                Object::Int(6),
+            ), vec!(
+                Code::Constant(2),
+                Code::SetGlobal(0),
+                Code::GetGlobal(0),
+                Code::Constant(3),
                 Code::Call(1),
                 Code::Pop,
             )),
+            ("1; 1; \"a\"; \"a\"; 1.5; 1.5;", vec!(
+                Object::Int(1),
+                Object::Str(String::from("a")),
+                Object::Float(1.5),
+                Object::Float(1.5),
+            ), vec!(
+                Code::Constant(0),
+                Code::Pop,
+                Code::Constant(0),
+                Code::Pop,
+                Code::Constant(1),
+                Code::Pop,
+                Code::Constant(1),
+                Code::Pop,
+                Code::Constant(2),
+                Code::Pop,
+                Code::Constant(3),
+                Code::Pop,
+            )),
         ];
-        for (input, expected) in test_array.iter() {
+        for (input, expected_constants, expected) in test_array.iter() {
             let lexer = Lexer::new(input);
             let parser = Parser::new(lexer);
             let symbol_table = SymbolTable::new(None);
-            let compiler = Compiler::new(parser, symbol_table);
-            let (output, _symbol_table) = compiler.run();
-            println!("Compiler: {:?} - {:?}", input, output);
+            let compiler = Compiler::new(parser, symbol_table, vec!()).unwrap();
+            let (output, _symbol_table, constants) = compiler.run().unwrap();
+            println!("Compiler: {:?} - {:?} {:?}", input, constants, output);
+            assert_eq!(expected_constants, &constants);
             assert_eq!(expected, &output);
         }
     }
+
+    #[test]
+    fn compiler_constants() {
+        // Constants passed in, like those of earlier REPL lines, are shared as well.
+        let constants = vec!(Object::Float(2.5), Object::Int(7), Object::Str(String::from("a")));
+        let compiler = Compiler::new(Parser::new(Lexer::new("\"a\"; 7; 8;")), SymbolTable::new(None), constants).unwrap();
+        let (output, _symbol_table, constants) = compiler.run().unwrap();
+        assert_eq!(output, vec!(
+            Code::Constant(2),
+            Code::Pop,
+            Code::Constant(1),
+            Code::Pop,
+            Code::Constant(3),
+            Code::Pop,
+        ));
+        assert_eq!(constants.len(), 4);
+    }
+
+    #[test]
+    fn compiler_program() {
+        let compile = |input: &str| {
+            Compiler::new(Parser::new(Lexer::new(input)), SymbolTable::new(None), vec!()).unwrap().compile_program()
+        };
+        assert_eq!(compile("let a = 1; a;"), Ok(Bytecode {
+            instructions: vec!(
+                Code::Constant(0),
+                Code::SetGlobal(0),
+                Code::GetGlobal(0),
+                Code::Pop,
+            ),
+            constants: vec!(Object::Int(1)),
+        }));
+        let test_array = [
+            ("a;", "Identifier a not found."),
+            ("let a = 1; fn() { a + b + c };", "Identifier b not found."),
+            ("x = 1;", "Identifier x not found."),
+            ("len = 1;", "Cannot reassign builtin len."),
+            ("let f = fn() { f = 1; };", "Cannot reassign function f."),
+            ("let a = [1]; a[0] = 2;", "Index assignments are not supported by the compiler yet."),
+            ("for (let i = 0; i < 1; i += 1) {}", "For loops are not supported by the compiler yet."),
+            ("[1, 2][1:];", "Slices are not supported by the compiler yet."),
+            ("99999999999999999999;", "Integer overflow."),
+            ("fn() { let a = { let x = 1; x * 3 }; a + x }();", "Identifier x not found."),
+            ("fn() { 9223372036854775808 };", "Integer overflow."),
+        ];
+        for (input, message) in test_array.iter() {
+            let result = compile(input);
+            println!("Compiler: {:?} - {:?}", input, result);
+            assert_eq!(result, Err(CompileError { message: String::from(*message) }));
+        }
+    }
 }
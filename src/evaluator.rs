@@ -1,13 +1,39 @@
+use std::collections::HashMap;
+use std::mem;
+
 use crate::ast::Expression;
 use crate::ast::Statement;
+use crate::lexer::Lexer;
 use crate::parser::Parser;
+use crate::parser::ParseError;
 use crate::object::Object;
 use crate::object::Environment;
+use crate::object::array_index;
+use crate::object::slice_range;
+use crate::object::repeat_str;
+use crate::object::float_operands;
+use crate::object::is_truthy;
+use crate::builtins::BUILTINS;
+use crate::builtins::call_builtin;
+use crate::builtins::check_arguments;
+
+// Builtins calling back into Monkey functions, so they are only available in the evaluator.
+const EVALUATOR_BUILTINS: [&str; 3] = ["map", "filter", "reduce"];
 
 const TRUE: Object = Object::Bool(true);
 const FALSE: Object = Object::Bool(false);
 const NULL: Object = Object::Null;
 
+// Return the object from the current function early if it is an error.
+macro_rules! try_eval {
+    ($obj:expr) => {
+        match $obj {
+            obj @ Object::Error(_) => return obj,
+            obj => obj,
+        }
+    };
+}
+
 pub struct Evaluator {
     input: Vec<Statement>,
     pos: usize,
@@ -16,14 +42,30 @@ pub struct Evaluator {
 }
 
 impl Evaluator {
-    pub fn new(parser: Parser, env: Environment) -> Evaluator {
-        let input = parser.collect();
-        Evaluator {
+    pub fn new(parser: Parser, env: Environment) -> Result<Evaluator, Vec<ParseError>> {
+        let (input, errors) = parser.parse_program();
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        Ok(Evaluator {
             input,
             pos: 0,
             returned: false,
             env,
+        })
+    }
+
+    // Evaluate the rest of the program, keeping only the value of the last expression statement,
+    // or the value of a top-level return or an error, like the popped value of the VM.
+    pub fn result(mut self) -> (Option<Object>, Environment) {
+        let mut result = None;
+        while let Some(stmt) = self.stmt() {
+            let (obj, _) = self.next().unwrap();
+            if self.returned || matches!(stmt, Statement::Expr(_)) {
+                result = Some(obj);
+            }
         }
+        (result, self.env)
     }
 
     fn stmt(&self) -> Option<Statement> {
@@ -37,23 +79,52 @@ impl Evaluator {
         }
     }
 
-    fn forward(&mut self) -> () {
+    fn forward(&mut self) {
         self.pos += 1;
     }
 
     fn eval_statement(&mut self, stmt: Statement, env: &mut Environment) -> Object {
         match stmt {
             Statement::Expr(expr) => self.eval_expression(expr, env),
-            Statement::Return(expr) => Object::Return(Box::new(self.eval_expression(expr, env))),
+            Statement::Return(expr) => {
+                let value = try_eval!(self.eval_expression(expr, env));
+                Object::Return(Box::new(value))
+            },
             Statement::Let { ident: Expression::Ident(ident), expr} => {
-                let value = self.eval_expression(expr, env);
+                let value = try_eval!(self.eval_expression(expr, env));
                 env.set(ident, value);
                 NULL
             },
+            Statement::Assign { ident, expr } => {
+                let value = try_eval!(self.eval_expression(expr, env));
+                self.eval_assign(ident, value, env)
+            },
+            Statement::While { condition, body } => self.eval_while(condition, *body, env),
+            Statement::DoWhile { body, condition } => self.eval_do_while(*body, condition, env),
+            Statement::For { init, condition, update, body } => {
+                self.eval_for(*init, condition, *update, *body, env)
+            },
             st => panic!("Invalid statement {:?}.", st),
         }
     }
 
+    fn eval_assign(&mut self, target: Expression, value: Object, env: &mut Environment) -> Object {
+        match target {
+            Expression::Ident(ident) => match env.reassign(ident, value) {
+                Ok(()) => NULL,
+                Err(msg) => Object::Error(msg),
+            },
+            Expression::Infix { operator, left, right } if operator == "[" => {
+                // Update a copy of the container, then assign it back to where it came from.
+                let container = try_eval!(self.eval_expression((*left).clone(), env));
+                let index = try_eval!(self.eval_expression(*right, env));
+                let container = try_eval!(set_index(container, index, value));
+                self.eval_assign(*left, container, env)
+            },
+            target => Object::Error(format!("Invalid assignment target {:?}.", target)),
+        }
+    }
+
     fn eval_block(&mut self, block: Statement, env: &mut Environment) -> Object {
         let block = match block {
             Statement::Block(v) => v,
@@ -62,7 +133,7 @@ impl Evaluator {
         let mut result = NULL;
         for stmt in block {
             result = self.eval_statement(*stmt, env);
-            if let Object::Return(_) = result {
+            if let Object::Return(_) | Object::Error(_) = result {
                 return result;
             }
         }
@@ -71,9 +142,14 @@ impl Evaluator {
 
     fn eval_expression(&mut self, expr: Expression, env: &mut Environment) -> Object {
         match expr {
-            Expression::Int(v) => Object::Int(i32::from_str_radix(&v, 10).unwrap()),
+            Expression::Int(v) => match v.parse() {
+                Ok(v) => Object::Int(v),
+                Err(_) => Object::Error(String::from("Integer overflow.")),
+            },
+            Expression::Float(v) => Object::Float(v.parse().unwrap()),
             Expression::Str(s) => Object::Str(s),
             Expression::Bool(v) => if &v == "true" { TRUE } else { FALSE },
+            Expression::Null => NULL,
             Expression::Prefix { operator, expr } => self.eval_prefix(operator, *expr, env),
             Expression::Infix { operator, left, right } => self.eval_infix(operator, *left, *right, env),
             Expression::If { condition, consequence, alternative } => {
@@ -82,14 +158,17 @@ impl Evaluator {
             Expression::Array(vec) => {
                 let mut obj_vec = Vec::new();
                 for expr in vec.into_iter() {
-                    let obj = self.eval_expression(*expr, env);
+                    let obj = try_eval!(self.eval_expression(*expr, env));
                     obj_vec.push(Box::new(obj));
                 }
                 Object::Array(obj_vec)
             },
+            Expression::Hash(pairs) => self.eval_hash(pairs, env),
             Expression::Ident(ident) => match env.get(&ident) {
-                Some(obj) => obj.clone(),
-                None => panic!("Identifier {:?} not found.", ident),
+                Some(obj) => obj,
+                None if BUILTINS.contains(&ident.as_str()) => Object::Builtin(ident),
+                None if EVALUATOR_BUILTINS.contains(&ident.as_str()) => Object::Builtin(ident),
+                None => Object::Error(format!("Identifier {} not found.", ident)),
             },
             Expression::Function { parameters, body } => Object::Function {
                 parameters,
@@ -99,90 +178,250 @@ impl Evaluator {
             Expression::Call { function, arguments } => {
                 self.eval_call(*function, arguments, env)
             },
+            // Bindings made in a block are local to it, like in Rust.
+            Expression::Block(block) => self.eval_block(*block, &mut Environment::init(env.clone())),
+            Expression::Slice { target, start, end } => self.eval_slice(*target, start, end, env),
+        }
+    }
+
+    fn eval_slice(&mut self, target: Expression, start: Option<Box<Expression>>,
+                  end: Option<Box<Expression>>, env: &mut Environment) -> Object {
+        let array = match try_eval!(self.eval_expression(target, env)) {
+            Object::Array(array) => array,
+            obj => return Object::Error(format!("Unsupported slice: {}.", obj)),
+        };
+        let start = match self.eval_slice_bound(start, 0, env) {
+            Ok(start) => start,
+            Err(err) => return err,
+        };
+        let end = match self.eval_slice_bound(end, array.len() as i64, env) {
+            Ok(end) => end,
+            Err(err) => return err,
+        };
+        let (start, end) = slice_range(start, end, array.len());
+        Object::Array(array[start..end].to_vec())
+    }
+
+    fn eval_slice_bound(&mut self, bound: Option<Box<Expression>>, default: i64,
+                        env: &mut Environment) -> Result<i64, Object> {
+        // A missing bound extends the slice to that end of the array.
+        let bound = match bound {
+            Some(bound) => bound,
+            None => return Ok(default),
+        };
+        match self.eval_expression(*bound, env) {
+            Object::Int(v) => Ok(v),
+            err @ Object::Error(_) => Err(err),
+            obj => Err(Object::Error(format!("Expect Object::Int, get {}.", obj))),
+        }
+    }
+
+    fn eval_hash(&mut self, pairs: Vec<(Box<Expression>, Box<Expression>)>,
+                 env: &mut Environment) -> Object {
+        let mut hash = HashMap::new();
+        for (key, value) in pairs.into_iter() {
+            let key = try_eval!(self.eval_expression(*key, env));
+            let key = match key.hashable() {
+                Some(key) => key,
+                None => return Object::Error(format!("Unusable as hash key: {}.", key)),
+            };
+            let value = try_eval!(self.eval_expression(*value, env));
+            // A repeated key overrides the previous value.
+            hash.insert(key, value);
         }
+        Object::Hash(hash)
     }
 
     fn eval_prefix(&mut self, op: String, expr: Expression, env: &mut Environment) -> Object {
-        let obj = self.eval_expression(expr, env);
+        if let ("-", Expression::Int(v)) = (op.as_str(), &expr) {
+            // Parse with the sign like the compiler, so the smallest integer does not overflow.
+            return match format!("-{}", v).parse() {
+                Ok(v) => Object::Int(v),
+                Err(_) => Object::Error(String::from("Integer overflow.")),
+            };
+        }
+        let obj = try_eval!(self.eval_expression(expr, env));
         match op.as_str() {
-            "!" => match obj {
-                TRUE => FALSE,
-                FALSE => TRUE,
-                NULL => TRUE,
-                _ => FALSE,
-            },
+            "!" => Object::Bool(!is_truthy(&obj)),
             "-" => match obj {
-                Object::Int(v) => Object::Int(-v),
-                _ => panic!("Invalid prefix operand {:?}.", obj),
+                Object::Int(v) => checked_int(v.checked_neg()),
+                Object::Float(v) => Object::Float(-v),
+                _ => Object::Error(format!("Unknown operator: -{}.", obj)),
             },
-            op => panic!("Invalid prefix operator {:?}.", op),
+            "~" => match obj {
+                Object::Int(v) => Object::Int(!v),
+                _ => Object::Error(format!("Unknown operator: ~{}.", obj)),
+            },
+            "+" => match obj {
+                Object::Int(_) | Object::Float(_) => obj,
+                _ => Object::Error(format!("Unknown operator: +{}.", obj)),
+            },
+            op => Object::Error(format!("Unknown operator: {}{}.", op, obj)),
         }
     }
 
     fn eval_infix(&mut self, op: String, left: Expression, right: Expression,
                   env: &mut Environment) -> Object {
-        let left = self.eval_expression(left, env);
-        let right = self.eval_expression(right, env);
-        if let Object::Int(l) = left {
-            if let Object::Int(r) = right {
-                match op.as_str() {
-                    "+" => Object::Int(l+r),
-                    "-" => Object::Int(l-r),
-                    "*" => Object::Int(l*r),
-                    "/" => Object::Int(l/r),
-                    "<" => if l < r { TRUE } else { FALSE },
-                    ">" => if l > r { TRUE } else { FALSE },
-                    "==" => if l == r { TRUE } else { FALSE },
-                    "!=" => if l != r { TRUE } else { FALSE },
-                    op => panic!("unknown operator {:?}", op),
-                }
-            } else { panic!("type mismatch") }
-        } else if let Object::Bool(l) = left {
-            if let Object::Bool(r) = right {
-                match op.as_str() {
-                    "==" => if l == r { TRUE } else { FALSE },
-                    "!=" => if l != r { TRUE } else { FALSE },
-                    op => panic!("unknown operator {:?}", op),
-                }
-            } else { panic!("type mismatch") }
-        } else if let Object::Str(l) = left {
-            if let Object::Str(r) = right {
-                match op.as_str() {
-                    "+" => Object::Str(l+r.as_str()),
-                    op => panic!("unknown operator {:?}", op),
+        let left = try_eval!(self.eval_expression(left, env));
+        // Short-circuit logical operators before evaluating the right operand.
+        match (op.as_str(), &left) {
+            ("&&", &FALSE) => return FALSE,
+            ("||", &TRUE) => return TRUE,
+            _ => (),
+        };
+        let right = try_eval!(self.eval_expression(right, env));
+        match (&left, &right) {
+            (Object::Str(l), &Object::Int(r)) if op == "[" => match l.chars().nth(r as usize) {
+                Some(ch) => Object::Str(ch.to_string()),
+                None => NULL,
+            },
+            (Object::Array(l), &Object::Int(r)) if op == "[" => match array_index(r, l.len()) {
+                Some(index) => (*l[index]).clone(),
+                None => NULL,
+            },
+            (Object::Hash(l), key) if op == "[" => match key.hashable() {
+                Some(key) => l.get(&key).cloned().unwrap_or(NULL),
+                None => Object::Error(format!("Unusable as hash key: {}.", key)),
+            },
+            _ if op == "[" => Object::Error(format!("Unsupported index: {}[{}].", left, right)),
+            // Anything can be compared with null, which only equals itself.
+            (Object::Null, _) | (_, Object::Null) if op == "==" => Object::Bool(left == right),
+            (Object::Null, _) | (_, Object::Null) if op == "!=" => Object::Bool(left != right),
+            (Object::Str(s), &Object::Int(n)) | (&Object::Int(n), Object::Str(s)) if op == "*" => {
+                match repeat_str(s, n) {
+                    Some(s) => Object::Str(s),
+                    None => Object::Error(String::from("String too long.")),
                 }
-            } else { panic!("type mismatch") }
-        } else if let Object::Array(l) = left {
-            if let Object::Int(r) = right {
+            },
+            (&Object::Int(l), &Object::Int(r)) => match op.as_str() {
+                "+" => checked_int(l.checked_add(r)),
+                "-" => checked_int(l.checked_sub(r)),
+                "*" => checked_int(l.checked_mul(r)),
+                "/" | "%" if r == 0 => Object::Error(String::from("Division by zero.")),
+                "/" => checked_int(l.checked_div(r)),
+                "%" => checked_int(l.checked_rem(r)),
+                "&" => Object::Int(l & r),
+                "|" => Object::Int(l | r),
+                "^" => Object::Int(l ^ r),
+                "<<" | ">>" if !(0..64).contains(&r) => Object::Error(format!("Invalid shift amount {}.", r)),
+                "<<" => Object::Int(l << r),
+                ">>" => Object::Int(l >> r),
+                "<" => Object::Bool(l < r),
+                ">" => Object::Bool(l > r),
+                "==" => Object::Bool(l == r),
+                "!=" => Object::Bool(l != r),
+                _ => unknown_operator(&left, &op, &right),
+            },
+            (&Object::Bool(l), &Object::Bool(r)) => match op.as_str() {
+                "==" => Object::Bool(l == r),
+                "!=" => Object::Bool(l != r),
+                "&&" => Object::Bool(l && r),
+                "||" => Object::Bool(l || r),
+                _ => unknown_operator(&left, &op, &right),
+            },
+            (l, r) if float_operands(l, r).is_some() => {
+                let (l, r) = float_operands(l, r).unwrap();
                 match op.as_str() {
-                    "[" => match l.get(r as usize) {
-                        Some(obj) => (**obj).clone(),
-                        None => NULL,
-                    },
-                    op => panic!("unknown operator {:?}", op),
+                    "+" => Object::Float(l + r),
+                    "-" => Object::Float(l - r),
+                    "*" => Object::Float(l * r),
+                    "/" => Object::Float(l / r),
+                    "%" => Object::Float(l % r),
+                    "<" => Object::Bool(l < r),
+                    ">" => Object::Bool(l > r),
+                    "==" => Object::Bool(l == r),
+                    "!=" => Object::Bool(l != r),
+                    _ => unknown_operator(&left, &op, &right),
                 }
-            } else { panic!("type mismatch") }
-        } else { panic!("unexpected type") }
+            },
+            (Object::Array(l), Object::Array(r)) if op == "+" => {
+                Object::Array(l.iter().chain(r.iter()).cloned().collect())
+            },
+            (Object::Str(l), Object::Str(r)) => match op.as_str() {
+                "+" => Object::Str(format!("{}{}", l, r)),
+                "<" => Object::Bool(l < r),
+                ">" => Object::Bool(l > r),
+                "==" => Object::Bool(l == r),
+                "!=" => Object::Bool(l != r),
+                _ => unknown_operator(&left, &op, &right),
+            },
+            _ if mem::discriminant(&left) == mem::discriminant(&right) => {
+                unknown_operator(&left, &op, &right)
+            },
+            _ => Object::Error(format!("Type mismatch: {} {} {}.", left, op, right)),
+        }
     }
 
     fn eval_if(&mut self, condition: Expression, consequence: Statement,
                alternative: Statement, env: &mut Environment) -> Object {
-        let condition = self.eval_expression(condition, env);
-        let block = match condition {
-            NULL | FALSE => alternative,
-            _ => consequence,
-        };
+        let condition = try_eval!(self.eval_expression(condition, env));
+        let block = if is_truthy(&condition) { consequence } else { alternative };
         self.eval_block(block, env)
     }
 
+    fn eval_while(&mut self, condition: Expression, body: Statement,
+                  env: &mut Environment) -> Object {
+        loop {
+            let condition = try_eval!(self.eval_expression(condition.clone(), env));
+            if !is_truthy(&condition) {
+                return NULL;
+            }
+            let result = self.eval_block(body.clone(), env);
+            if let Object::Return(_) | Object::Error(_) = result {
+                return result;
+            }
+        }
+    }
+
+    fn eval_do_while(&mut self, body: Statement, condition: Expression,
+                     env: &mut Environment) -> Object {
+        // Run the body once before checking the condition like a while loop.
+        let result = self.eval_block(body.clone(), env);
+        if let Object::Return(_) | Object::Error(_) = result {
+            return result;
+        }
+        self.eval_while(condition, body, env)
+    }
+
+    fn eval_for(&mut self, init: Statement, condition: Expression, update: Statement,
+                body: Statement, env: &mut Environment) -> Object {
+        // Run the loop as init + while, keeping the loop variable in a child environment.
+        let mut loop_env = Environment::init(env.clone());
+        match self.eval_statement(init, &mut loop_env) {
+            err @ Object::Error(_) => err,
+            _ => {
+                let mut block = match body {
+                    Statement::Block(v) => v,
+                    _ => panic!("Invalid block statement."),
+                };
+                block.push(Box::new(update));
+                self.eval_while(condition, Statement::Block(block), &mut loop_env)
+            },
+        }
+    }
+
     fn eval_call(&mut self, function: Expression, arguments: Vec<Box<Expression>>,
                  env: &mut Environment) -> Object {
-        let function = self.eval_expression(function, env);
+        let function = try_eval!(self.eval_expression(function, env));
+        let mut objs = Vec::new();
+        for arg in arguments.into_iter() {
+            objs.push(try_eval!(self.eval_expression(*arg, env)));
+        }
+        self.apply_function(function, objs)
+    }
+
+    fn apply_function(&mut self, function: Object, objs: Vec<Object>) -> Object {
+        if let Object::Builtin(name) = function {
+            if EVALUATOR_BUILTINS.contains(&name.as_str()) {
+                return self.call_evaluator_builtin(&name, objs);
+            }
+            return call_builtin(&name, objs);
+        }
         if let Object::Function { parameters, body, env: fn_env } = function {
             let mut extended_fn_env = Environment::init(fn_env);
-            for (par, aug) in parameters.into_iter().zip(arguments.into_iter()) {
+            for (par, obj) in parameters.into_iter().zip(objs) {
                 if let Expression::Ident(name) = *par {
-                    extended_fn_env.set(name, self.eval_expression(*aug, env));
+                    extended_fn_env.set(name, obj);
                 } else {
                     panic!("Invalid parameter {:?}.", par);
                 }
@@ -194,8 +433,107 @@ impl Evaluator {
                 result
             }
         } else {
-            panic!("Invalid function {:?}.", function);
+            Object::Error(format!("Not a function: {}.", function))
+        }
+    }
+
+    fn call_evaluator_builtin(&mut self, name: &str, arguments: Vec<Object>) -> Object {
+        match name {
+            "map" => self.builtin_map(arguments),
+            "filter" => self.builtin_filter(arguments),
+            "reduce" => self.builtin_reduce(arguments),
+            name => Object::Error(format!("Unknown builtin {}.", name)),
+        }
+    }
+
+    fn builtin_map(&mut self, arguments: Vec<Object>) -> Object {
+        if let Some(err) = check_arguments("map", &arguments, 2) {
+            return err;
+        }
+        let vec = match check_array_and_function("map", &arguments[0], &arguments[1]) {
+            Ok(vec) => vec,
+            Err(err) => return err,
+        };
+        let mut result = Vec::new();
+        for obj in vec.into_iter() {
+            let obj = try_eval!(self.apply_function(arguments[1].clone(), vec!(*obj)));
+            result.push(Box::new(obj));
+        }
+        Object::Array(result)
+    }
+
+    fn builtin_filter(&mut self, arguments: Vec<Object>) -> Object {
+        if let Some(err) = check_arguments("filter", &arguments, 2) {
+            return err;
         }
+        let vec = match check_array_and_function("filter", &arguments[0], &arguments[1]) {
+            Ok(vec) => vec,
+            Err(err) => return err,
+        };
+        let mut result = Vec::new();
+        for obj in vec.into_iter() {
+            let keep = try_eval!(self.apply_function(arguments[1].clone(), vec!((*obj).clone())));
+            if is_truthy(&keep) {
+                result.push(obj);
+            }
+        }
+        Object::Array(result)
+    }
+
+    fn builtin_reduce(&mut self, arguments: Vec<Object>) -> Object {
+        if let Some(err) = check_arguments("reduce", &arguments, 3) {
+            return err;
+        }
+        let vec = match check_array_and_function("reduce", &arguments[0], &arguments[2]) {
+            Ok(vec) => vec,
+            Err(err) => return err,
+        };
+        let mut acc = arguments[1].clone();
+        for obj in vec.into_iter() {
+            acc = try_eval!(self.apply_function(arguments[2].clone(), vec!(acc, *obj)));
+        }
+        acc
+    }
+}
+
+fn check_array_and_function(name: &str, array: &Object, function: &Object) -> Result<Vec<Box<Object>>, Object> {
+    // Return the elements of the array, or an error object if the arguments have wrong types.
+    match (array, function) {
+        (Object::Array(vec), Object::Function { .. }) | (Object::Array(vec), Object::Builtin(_)) => Ok(vec.clone()),
+        (Object::Array(_), obj) => Err(Object::Error(format!("{} expects Object::Function, get {}.", name, obj))),
+        (obj, _) => Err(Object::Error(format!("{} expects Object::Array, get {}.", name, obj))),
+    }
+}
+
+fn unknown_operator(left: &Object, op: &str, right: &Object) -> Object {
+    Object::Error(format!("Unknown operator: {} {} {}.", left, op, right))
+}
+
+fn set_index(container: Object, index: Object, value: Object) -> Object {
+    match (container, index) {
+        (Object::Array(mut vec), Object::Int(i)) => match array_index(i, vec.len()) {
+            Some(i) => {
+                *vec[i] = value;
+                Object::Array(vec)
+            },
+            None => Object::Error(format!("Index out of range: {}.", i)),
+        },
+        (Object::Hash(mut hash), key) => match key.hashable() {
+            Some(k) => {
+                hash.insert(k, value);
+                Object::Hash(hash)
+            },
+            None => Object::Error(format!("Unusable as hash key: {}.", key)),
+        },
+        (container, index) => Object::Error(format!("Unsupported index assignment: {}[{}].", container, index)),
+    }
+}
+
+fn checked_int(value: Option<i64>) -> Object {
+    // Turn the result of checked integer arithmetic into an object.
+    match value {
+        Some(v) => Object::Int(v),
+        None => Object::Error(String::from("Integer overflow.")),
     }
 }
 
@@ -208,12 +546,16 @@ impl Iterator for Evaluator {
             Some(stmt) => {
                 self.forward();
                 // We cannot just pass self.env around, or there will be 2 mutable borrows of self.
+                // Cloning it is cheap and the bindings are shared.
                 let mut env = self.env.clone();
                 let result = self.eval_statement(stmt, &mut env);
-                self.env = env;
                 if let Object::Return(obj) = result {
                     self.returned = true;
                     Some((*obj, self.env.clone()))
+                } else if let Object::Error(_) = result {
+                    // Stop evaluating the rest of the program after an error.
+                    self.returned = true;
+                    Some((result, self.env.clone()))
                 } else {
                     Some((result, self.env.clone()))
                 }
@@ -223,6 +565,23 @@ impl Iterator for Evaluator {
     }
 }
 
+// Evaluate a program in a fresh environment, returning its result like `Evaluator::result`,
+// or the parse errors and the runtime error formatted as messages.
+pub fn eval(input: &str) -> Result<Object, String> {
+    let evaluator = match Evaluator::new(Parser::new(Lexer::new(input)), Environment::new()) {
+        Ok(evaluator) => evaluator,
+        Err(errors) => {
+            let messages: Vec<String> = errors.iter().map(|err| err.to_string()).collect();
+            return Err(messages.join("\n"));
+        },
+    };
+    match evaluator.result() {
+        (Some(err @ Object::Error(_)), _) => Err(err.to_string()),
+        (Some(obj), _) => Ok(obj),
+        (None, _) => Ok(NULL),
+    }
+}
+
 
 #[cfg(test)]
 mod tests {
@@ -234,6 +593,9 @@ mod tests {
     use super::Parser;
     use super::Object;
     use super::Evaluator;
+    use super::eval;
+    use super::HashMap;
+    use crate::object::HashKey;
 
     #[test]
     fn evaluator() {
@@ -251,6 +613,14 @@ mod tests {
             ("2 - 1;", Object::Int(1), "1"),
             ("2 * 1;", Object::Int(2), "2"),
             ("2 / 1;", Object::Int(2), "2"),
+            ("5 % 3;", Object::Int(2), "2"),
+            ("1 / 0;", Object::Error(String::from("Division by zero.")), "Error: Division by zero."),
+            ("1 % 0;", Object::Error(String::from("Division by zero.")), "Error: Division by zero."),
+            ("100000 * 100000;", Object::Int(10000000000), "10000000000"),
+            ("2147483647 + 1;", Object::Int(2147483648), "2147483648"),
+            ("4000000000 * 4000000000;", Object::Error(String::from("Integer overflow.")), "Error: Integer overflow."),
+            ("9223372036854775807 + 1;", Object::Error(String::from("Integer overflow.")), "Error: Integer overflow."),
+            ("-9223372036854775807 - 2;", Object::Error(String::from("Integer overflow.")), "Error: Integer overflow."),
             ("1 + 2 * 3;", Object::Int(7), "7"),
             ("(1 + 2) * 3;", Object::Int(9), "9"),
 
@@ -261,6 +631,10 @@ mod tests {
 
             ("true == false;", Object::Bool(false), "false"),
             ("(1 < 2) != false;", Object::Bool(true), "true"),
+            ("true && false;", Object::Bool(false), "false"),
+            ("false || 1 < 2;", Object::Bool(true), "true"),
+            ("false && undefined;", Object::Bool(false), "false"),
+            ("true || undefined;", Object::Bool(true), "true"),
 
             ("if (true) { 1 };", Object::Int(1), "1"),
             ("if (false) { 1 };", Object::Null, "Null"),
@@ -268,9 +642,68 @@ mod tests {
             ("if (0) { 1 };", Object::Int(1), "1"),
             ("if (1 < 2) { 1 } else { 2 };", Object::Int(1), "1"),
             ("if (1 > 2) { 1 } else { 2 };", Object::Int(2), "2"),
+            ("if (false) { 1 } else if (true) { 2 } else { 3 };", Object::Int(2), "2"),
+            ("if (false) { 1 } else if (false) { 2 } else { 3 };", Object::Int(3), "3"),
 
             ("return 10; 5;", Object::Int(10), "10"),
 
+            ("let x = 1; x = 2; x;", Object::Int(2), "2"),
+            ("x = 2;", Object::Error(String::from("Identifier x not found.")),
+             "Error: Identifier x not found."),
+            ("undefinedVar;", Object::Error(String::from("Identifier undefinedVar not found.")),
+             "Error: Identifier undefinedVar not found."),
+            ("let len = fn(x) { 0 }; len(\"abc\");", Object::Int(0), "0"),
+            ("let f = fn(x) { x = x + 1; x }; f(1);", Object::Int(2), "2"),
+            ("let x = 1; x += 4; x;", Object::Int(5), "5"),
+            ("let x = 1; x -= 4; x;", Object::Int(-3), "-3"),
+            ("let x = 3; x *= 1 + 1; x;", Object::Int(6), "6"),
+            ("let x = 7; x /= 2; x;", Object::Int(3), "3"),
+            ("let s = \"a\"; s += \"b\"; s;", Object::Str(String::from("ab")), "ab"),
+            ("x += 1;", Object::Error(String::from("Identifier x not found.")),
+             "Error: Identifier x not found."),
+            ("let a = [1, 2]; a[0] = 9; a;", Object::Array(vec!(
+                Box::new(Object::Int(9)), Box::new(Object::Int(2)),
+            )), "[9, 2]"),
+            ("let a = [1, 2]; a[-1] += 3; a;", Object::Array(vec!(
+                Box::new(Object::Int(1)), Box::new(Object::Int(5)),
+            )), "[1, 5]"),
+            ("let a = [1, 2]; let b = a; a[0] = 9; b;", Object::Array(vec!(
+                Box::new(Object::Int(1)), Box::new(Object::Int(2)),
+            )), "[1, 2]"),
+            ("let a = [[1], [2]]; a[1][0] = 3; a;", Object::Array(vec!(
+                Box::new(Object::Array(vec!(Box::new(Object::Int(1))))),
+                Box::new(Object::Array(vec!(Box::new(Object::Int(3))))),
+            )), "[[1], [3]]"),
+            ("let a = [1]; a[1] = 2;", Object::Error(String::from("Index out of range: 1.")),
+             "Error: Index out of range: 1."),
+            ("let h = {\"a\": 1}; h[\"b\"] = 2; h[\"a\"] = 3; h;", Object::Hash(HashMap::from([
+                (HashKey::Str(String::from("a")), Object::Int(3)),
+                (HashKey::Str(String::from("b")), Object::Int(2)),
+            ])), "{a: 3, b: 2}"),
+            ("let h = {}; h[[]] = 1;", Object::Error(String::from("Unusable as hash key: [].")),
+             "Error: Unusable as hash key: []."),
+            ("let s = \"ab\"; s[0] = \"c\";", Object::Error(String::from("Unsupported index assignment: ab[0].")),
+             "Error: Unsupported index assignment: ab[0]."),
+            ("a[0] = 1;", Object::Error(String::from("Identifier a not found.")),
+             "Error: Identifier a not found."),
+            ("let x = 1; x /= 0;", Object::Error(String::from("Division by zero.")),
+             "Error: Division by zero."),
+
+            ("let i = 0; while (i < 3) { let i = i + 1; } i;", Object::Int(3), "3"),
+            ("let i = 0; while (i < 3) { i = i + 1; } i;", Object::Int(3), "3"),
+            ("while (false) { 1; }", Object::Null, "Null"),
+            ("let f = fn() { while (true) { return 1; } }; f();", Object::Int(1), "1"),
+            ("let i = 0; do { i = i + 1; } while (false); i;", Object::Int(1), "1"),
+            ("let i = 0; do { i = i + 1; } while (i < 3); i;", Object::Int(3), "3"),
+            ("do { 1; } while (false);", Object::Null, "Null"),
+            ("let f = fn() { do { return 2; } while (true); }; f();", Object::Int(2), "2"),
+            ("let sum = 0; for (let i = 1; i < 5; i = i + 1) { sum = sum + i; } sum;",
+             Object::Int(10), "10"),
+            ("for (let i = 0; i < 3; i = i + 1) {} i;",
+             Object::Error(String::from("Identifier i not found.")), "Error: Identifier i not found."),
+            ("let f = fn() { for (let i = 0; true; i = i + 1) { if (i == 2) { return i; } } }; f();",
+             Object::Int(2), "2"),
+
             ("let a = 5; a;", Object::Int(5), "5"),
             ("let a = 5; let b = a + 5; b;", Object::Int(10), "10"),
 
@@ -278,7 +711,7 @@ mod tests {
                 parameters: Vec::new(),
                 body: Box::new(Statement::Block(Vec::new())),
                 env: Environment::new(),
-            }, "function"),
+            }, "fn() { ... }"),
             ("fn(x, y) { x };", Object::Function {
                 parameters: vec!(
                     Box::new(Expression::Ident(String::from("x"))),
@@ -288,22 +721,289 @@ mod tests {
                     Box::new(Statement::Expr(Expression::Ident(String::from("x")))),
                 ))),
                 env: Environment::new(),
-            }, "function"),
+            }, "fn(x, y) { ... }"),
 
             ("let add = fn(x, y) { x + y;}; add(1, add(2, 3));", Object::Int(6), "6"),
+            ("
+                let counter = fn() { let c = 0; fn() { c = c + 1; c } };
+                let inc = counter();
+                inc();
+                inc();
+            ", Object::Int(2), "2"),
+            ("let f = fn() { f }; f() == f;",
+             Object::Error(String::from("Unknown operator: fn() { ... } == fn() { ... }.")),
+             "Error: Unknown operator: fn() { ... } == fn() { ... }."),
             ("fn(x, y) { x + y;}(1, 2);", Object::Int(3), "3"),
 
             ("\"a b\";", Object::Str(String::from("a b")), "a b"),
             ("\"a\" + \"b\";", Object::Str(String::from("ab")), "ab"),
+            ("\"a\" == \"a\";", Object::Bool(true), "true"),
+            ("\"a\" != \"b\";", Object::Bool(true), "true"),
+            ("\"a\" == \"b\";", Object::Bool(false), "false"),
+            ("\"abc\" < \"abd\";", Object::Bool(true), "true"),
+            ("\"b\" > \"abc\";", Object::Bool(true), "true"),
+            ("\"ab\" > \"abc\";", Object::Bool(false), "false"),
+            ("\"a\" < 1;", Object::Error(String::from("Type mismatch: a < 1.")),
+             "Error: Type mismatch: a < 1."),
+            ("\"a\" == 1;", Object::Error(String::from("Type mismatch: a == 1.")),
+             "Error: Type mismatch: a == 1."),
 
             ("let arr = [\"s\", 1]; arr[1];", Object::Int(1), "1"),
+            ("~0 == -1;", Object::Bool(true), "true"),
+            ("~5;", Object::Int(-6), "-6"),
+            ("~true;", Object::Error(String::from("Unknown operator: ~true.")), "Error: Unknown operator: ~true."),
+            ("let m = [[1, 2], [3, 4]]; m[1][0];", Object::Int(3), "3"),
+            ("let m = [[1, 2], [3, 4]]; m[0][-1] + m[-1][0];", Object::Int(5), "5"),
+            ("{\"a\": [1, {\"b\": 5}]}[\"a\"][1][\"b\"];", Object::Int(5), "5"),
+            ("[[1]][0][1];", Object::Null, "Null"),
+            ("let m = [[1, 2], [3, 4]]; let i = [1, 0]; m[i[0]][i[1] + 1];", Object::Int(4), "4"),
+            ("{ let x = 1; x + 1 };", Object::Int(2), "2"),
+            ("let a = { let x = 1; x * 3 }; a + x;", Object::Error(String::from("Identifier x not found.")),
+             "Error: Identifier x not found."),
+            ("let x = 1; let a = { let x = 2; x * 3 }; a + x;", Object::Int(7), "7"),
+            ("let y = 1; { y = 2; }; y;", Object::Int(2), "2"),
+            ("{ 1; let y = 2; };", Object::Null, "Null"),
+            ("{a: 1};", Object::Error(String::from("Identifier a not found.")), "Error: Identifier a not found."),
+            ("let a = 1; {a: 1}[1];", Object::Int(1), "1"),
+            ("+5;", Object::Int(5), "5"),
+            ("+-1.5;", Object::Float(-1.5), "-1.5"),
+            ("1 - +2;", Object::Int(-1), "-1"),
+            ("+true;", Object::Error(String::from("Unknown operator: +true.")), "Error: Unknown operator: +true."),
+            ("let x = 1; x > 0 ? \"pos\" : \"neg\";", Object::Str(String::from("pos")), "pos"),
+            ("let x = -1; x > 0 ? \"pos\" : \"neg\";", Object::Str(String::from("neg")), "neg"),
+            ("let x = 0; x > 0 ? 1 : x < 0 ? -1 : 0;", Object::Int(0), "0"),
+            ("null;", Object::Null, "Null"),
+            ("null == null;", Object::Bool(true), "true"),
+            ("null != null;", Object::Bool(false), "false"),
+            ("let x = 1; x == null;", Object::Bool(false), "false"),
+            ("[] != null;", Object::Bool(true), "true"),
+            ("first([]) == null;", Object::Bool(true), "true"),
+            ("null < 1;", Object::Error(String::from("Type mismatch: Null < 1.")), "Error: Type mismatch: Null < 1."),
+            ("type(null);", Object::Str(String::from("null")), "null"),
+            ("!null;", Object::Bool(true), "true"),
+            ("6 & 3;", Object::Int(2), "2"),
+            ("6 | 3;", Object::Int(7), "7"),
+            ("6 ^ 3;", Object::Int(5), "5"),
+            ("1 << 4;", Object::Int(16), "16"),
+            ("-16 >> 2;", Object::Int(-4), "-4"),
+            ("1 | 2 == 3;", Object::Bool(true), "true"),
+            ("1 << 32;", Object::Int(4294967296), "4294967296"),
+            ("1 << 64;", Object::Error(String::from("Invalid shift amount 64.")), "Error: Invalid shift amount 64."),
+            ("1 >> -1;", Object::Error(String::from("Invalid shift amount -1.")), "Error: Invalid shift amount -1."),
+            ("1.0 & 1;", Object::Error(String::from("Unknown operator: 1.0 & 1.")), "Error: Unknown operator: 1.0 & 1."),
+            ("\"ab\" * 3;", Object::Str(String::from("ababab")), "ababab"),
+            ("3 * \"ab\";", Object::Str(String::from("ababab")), "ababab"),
+            ("\"ab\" * -1;", Object::Str(String::new()), ""),
+            ("\"ab\" * 9223372036854775807;", Object::Error(String::from("String too long.")), "Error: String too long."),
+            ("1000000000 * \"ab\";", Object::Error(String::from("String too long.")), "Error: String too long."),
+            ("\"ab\" * 1.5;", Object::Error(String::from("Type mismatch: ab * 1.5.")), "Error: Type mismatch: ab * 1.5."),
+            ("\"ab\" * \"c\";", Object::Error(String::from("Unknown operator: ab * c.")), "Error: Unknown operator: ab * c."),
+            ("[1, 2] + [3, 4];", Object::Array(vec!(
+                Box::new(Object::Int(1)), Box::new(Object::Int(2)),
+                Box::new(Object::Int(3)), Box::new(Object::Int(4)),
+            )), "[1, 2, 3, 4]"),
+            ("let a = [1]; let b = [2]; let c = a + b; a;", Object::Array(vec!(Box::new(Object::Int(1)))), "[1]"),
+            ("let a = [1]; let b = [2]; let c = a + b; b;", Object::Array(vec!(Box::new(Object::Int(2)))), "[2]"),
+            ("[1] + 2;", Object::Error(String::from("Type mismatch: [1] + 2.")), "Error: Type mismatch: [1] + 2."),
+            ("[1] - [2];", Object::Error(String::from("Unknown operator: [1] - [2].")),
+             "Error: Unknown operator: [1] - [2]."),
+            ("[1, 2, 3][-1];", Object::Int(3), "3"),
+            ("1.5;", Object::Float(1.5), "1.5"),
+            ("-1.5;", Object::Float(-1.5), "-1.5"),
+            ("1.5 + 1;", Object::Float(2.5), "2.5"),
+            ("3.0 / 2.0;", Object::Float(1.5), "1.5"),
+            ("2 * 1.5 - 1;", Object::Float(2.0), "2.0"),
+            ("1.0 / 0;", Object::Float(f64::INFINITY), "inf"),
+            ("1.5 < 2;", Object::Bool(true), "true"),
+            ("1.5 > 2.5;", Object::Bool(false), "false"),
+            ("2.0 == 2;", Object::Bool(true), "true"),
+            ("1.5 != 1.5;", Object::Bool(false), "false"),
+            ("1.5 && 1.0;", Object::Error(String::from("Unknown operator: 1.5 && 1.0.")),
+             "Error: Unknown operator: 1.5 && 1.0."),
+            ("[1, 2, 3][-3];", Object::Int(1), "1"),
+            ("[1, 2, 3][-4];", Object::Null, "Null"),
+            ("[1, 2, 3][3];", Object::Null, "Null"),
             ("let arr = [\"s\", 1]; arr[2];", Object::Null, "Null"),
+            ("let arr = [1, 2, 3, 4]; arr[1:3];", Object::Array(vec!(
+                Box::new(Object::Int(2)), Box::new(Object::Int(3)),
+            )), "[2, 3]"),
+            ("[1, 2, 3][:2];", Object::Array(vec!(Box::new(Object::Int(1)), Box::new(Object::Int(2)))), "[1, 2]"),
+            ("[1, 2, 3][2:];", Object::Array(vec!(Box::new(Object::Int(3)))), "[3]"),
+            ("[1, 2, 3][:];", Object::Array(vec!(
+                Box::new(Object::Int(1)), Box::new(Object::Int(2)), Box::new(Object::Int(3)),
+            )), "[1, 2, 3]"),
+            ("[1, 2, 3][-2:10];", Object::Array(vec!(Box::new(Object::Int(2)), Box::new(Object::Int(3)))), "[2, 3]"),
+            ("[1, 2, 3][-10:1];", Object::Array(vec!(Box::new(Object::Int(1)))), "[1]"),
+            ("[1, 2, 3][2:1];", Object::Array(vec!()), "[]"),
+            ("[1, 2, 3][5:];", Object::Array(vec!()), "[]"),
+            ("let i = 1; [1, 2, 3][i:i + 1];", Object::Array(vec!(Box::new(Object::Int(2)))), "[2]"),
+            ("\"abc\"[1:];", Object::Error(String::from("Unsupported slice: abc.")), "Error: Unsupported slice: abc."),
+            ("[1][\"a\":];", Object::Error(String::from("Expect Object::Int, get a.")), "Error: Expect Object::Int, get a."),
+            ("[1][:1 / 0];", Object::Error(String::from("Division by zero.")), "Error: Division by zero."),
+
+            ("5 + true;", Object::Error(String::from("Type mismatch: 5 + true.")),
+             "Error: Type mismatch: 5 + true."),
+            ("5 + true; 5;", Object::Error(String::from("Type mismatch: 5 + true.")),
+             "Error: Type mismatch: 5 + true."),
+            ("true + false;", Object::Error(String::from("Unknown operator: true + false.")),
+             "Error: Unknown operator: true + false."),
+            ("-true;", Object::Error(String::from("Unknown operator: -true.")),
+             "Error: Unknown operator: -true."),
+            ("\"a\" - \"b\";", Object::Error(String::from("Unknown operator: a - b.")),
+             "Error: Unknown operator: a - b."),
+            ("if (10 > 1) { true + false; 10 }", Object::Error(String::from("Unknown operator: true + false.")),
+             "Error: Unknown operator: true + false."),
+            ("let f = fn() { let a = -true; 1 }; f();", Object::Error(String::from("Unknown operator: -true.")),
+             "Error: Unknown operator: -true."),
+            ("[1, 1 / 0, 2];", Object::Error(String::from("Division by zero.")),
+             "Error: Division by zero."),
+            ("len(1 / 0);", Object::Error(String::from("Division by zero.")),
+             "Error: Division by zero."),
+            ("{fn() {}: 1};", Object::Error(String::from("Unusable as hash key: fn() { ... }.")),
+             "Error: Unusable as hash key: fn() { ... }."),
+            ("1[0];", Object::Error(String::from("Unsupported index: 1[0].")),
+             "Error: Unsupported index: 1[0]."),
+            ("1();", Object::Error(String::from("Not a function: 1.")),
+             "Error: Not a function: 1."),
+
+            ("{};", Object::Hash(HashMap::new()), "{}"),
+            ("let k = \"b\"; {1: 2, k: 1 + 2, true: \"t\", 1: 4};", Object::Hash(HashMap::from([
+                (HashKey::Int(1), Object::Int(4)),
+                (HashKey::Str(String::from("b")), Object::Int(3)),
+                (HashKey::Bool(true), Object::Str(String::from("t"))),
+            ])), "{1: 4, b: 3, true: t}"),
+            ("{\"a\": 1}[\"a\"];", Object::Int(1), "1"),
+            ("{\"a\": 1}[[]];", Object::Error(String::from("Unusable as hash key: [].")),
+             "Error: Unusable as hash key: []."),
+            ("{\"a\": 1}[\"b\"];", Object::Null, "Null"),
+            ("\"hello\"[1];", Object::Str(String::from("e")), "e"),
+            ("\"hello\"[5];", Object::Null, "Null"),
+
+            ("len;", Object::Builtin(String::from("len")), "builtin function len"),
+            ("len(\"hello\");", Object::Int(5), "5"),
+            ("len([1, 2, 3]);", Object::Int(3), "3"),
+            ("len(1);", Object::Error(String::from("len expects Object::Str or Object::Array, get 1.")),
+             "Error: len expects Object::Str or Object::Array, get 1."),
+            ("len(\"a\", \"b\");", Object::Error(String::from("len expects 1 arguments, get 2.")),
+             "Error: len expects 1 arguments, get 2."),
+
+            ("first([1, 2, 3]);", Object::Int(1), "1"),
+            ("first([]);", Object::Null, "Null"),
+            ("first(1);", Object::Error(String::from("first expects Object::Array, get 1.")),
+             "Error: first expects Object::Array, get 1."),
+            ("last([1, 2, 3]);", Object::Int(3), "3"),
+            ("last([]);", Object::Null, "Null"),
+            ("last(1);", Object::Error(String::from("last expects Object::Array, get 1.")),
+             "Error: last expects Object::Array, get 1."),
+            ("rest([1, 2, 3]);", Object::Array(vec!(
+                Box::new(Object::Int(2)),
+                Box::new(Object::Int(3)),
+            )), "[2, 3]"),
+            ("rest([]);", Object::Null, "Null"),
+            ("rest(1);", Object::Error(String::from("rest expects Object::Array, get 1.")),
+             "Error: rest expects Object::Array, get 1."),
+            ("push([1], 2);", Object::Array(vec!(
+                Box::new(Object::Int(1)),
+                Box::new(Object::Int(2)),
+            )), "[1, 2]"),
+            ("let a = [1]; let b = push(a, 2); a;", Object::Array(vec!(
+                Box::new(Object::Int(1)),
+            )), "[1]"),
+            ("push(1, 2);", Object::Error(String::from("push expects Object::Array, get 1.")),
+             "Error: push expects Object::Array, get 1."),
+            ("puts(1, \"a\");", Object::Null, "Null"),
+            ("let f = fn(x) { puts(x); x }; f(2);", Object::Int(2), "2"),
+            ("type(1);", Object::Str(String::from("int")), "int"),
+            ("type(1.5);", Object::Str(String::from("float")), "float"),
+            ("type(\"a\");", Object::Str(String::from("str")), "str"),
+            ("type(true);", Object::Str(String::from("bool")), "bool"),
+            ("type(if (false) { 1 });", Object::Str(String::from("null")), "null"),
+            ("type([]);", Object::Str(String::from("array")), "array"),
+            ("type({});", Object::Str(String::from("hash")), "hash"),
+            ("type(fn() {});", Object::Str(String::from("function")), "function"),
+            ("type(len);", Object::Str(String::from("function")), "function"),
+            ("str(42);", Object::Str(String::from("42")), "42"),
+            ("str([1, true]) + \"!\";", Object::Str(String::from("[1, true]!")), "[1, true]!"),
+            ("int(\"7\") + 1;", Object::Int(8), "8"),
+            ("int(\"-7\");", Object::Int(-7), "-7"),
+            ("int(\"x\");", Object::Error(String::from("Cannot convert \"x\" to an integer.")),
+             "Error: Cannot convert \"x\" to an integer."),
+            ("int(\"99999999999\");", Object::Int(99999999999), "99999999999"),
+            ("99999999999999999999;", Object::Error(String::from("Integer overflow.")), "Error: Integer overflow."),
+            ("-9223372036854775808;", Object::Int(i64::MIN), "-9223372036854775808"),
+            ("-(-9223372036854775808);", Object::Error(String::from("Integer overflow.")), "Error: Integer overflow."),
+            ("[1, 9223372036854775808];", Object::Error(String::from("Integer overflow.")), "Error: Integer overflow."),
+            ("int(\"99999999999999999999\");",
+             Object::Error(String::from("Cannot convert \"99999999999999999999\" to an integer.")),
+             "Error: Cannot convert \"99999999999999999999\" to an integer."),
+            ("int(true);", Object::Error(String::from("int expects Object::Int or Object::Str, get true.")),
+             "Error: int expects Object::Int or Object::Str, get true."),
+            ("type();", Object::Error(String::from("type expects 1 arguments, get 0.")),
+             "Error: type expects 1 arguments, get 0."),
+
+            ("join([\"a\", \"b\", \"c\"], \"-\") == \"a-b-c\";", Object::Bool(true), "true"),
+            ("join([], \", \");", Object::Str(String::new()), ""),
+            ("join(map([1, 2], str), \"\");", Object::Str(String::from("12")), "12"),
+            ("join([\"a\", 1], \"\");", Object::Error(String::from("join expects an array of Object::Str, get 1.")),
+             "Error: join expects an array of Object::Str, get 1."),
+            ("join([\"a\"], 1);", Object::Error(String::from("join expects Object::Str, get 1.")),
+             "Error: join expects Object::Str, get 1."),
+
+            ("contains([1, \"a\", [2]], \"a\");", Object::Bool(true), "true"),
+            ("contains([1, \"a\", [2]], [2]);", Object::Bool(true), "true"),
+            ("contains([1, \"a\", [2]], 2);", Object::Bool(false), "false"),
+            ("contains([], null);", Object::Bool(false), "false"),
+            ("contains({\"a\": 1, 2: 3}, \"a\");", Object::Bool(true), "true"),
+            ("contains({\"a\": 1, 2: 3}, 1);", Object::Bool(false), "false"),
+            ("contains({}, []);", Object::Error(String::from("Unusable as hash key: [].")),
+             "Error: Unusable as hash key: []."),
+            ("contains(\"abc\", \"a\");",
+             Object::Error(String::from("contains expects Object::Array or Object::Hash, get abc.")),
+             "Error: contains expects Object::Array or Object::Hash, get abc."),
+
+            ("map([1, 2, 3], fn(x) { x * 2 });", Object::Array(vec!(
+                Box::new(Object::Int(2)),
+                Box::new(Object::Int(4)),
+                Box::new(Object::Int(6)),
+            )), "[2, 4, 6]"),
+            ("map([], fn(x) { x });", Object::Array(vec!()), "[]"),
+            ("map([\"a\", \"bc\"], len);", Object::Array(vec!(
+                Box::new(Object::Int(1)),
+                Box::new(Object::Int(2)),
+            )), "[1, 2]"),
+            ("map([1], fn(x) { return x + 1; x; });", Object::Array(vec!(Box::new(Object::Int(2)))), "[2]"),
+            ("map([1], 1);", Object::Error(String::from("map expects Object::Function, get 1.")),
+             "Error: map expects Object::Function, get 1."),
+            ("map(1, fn(x) { x });", Object::Error(String::from("map expects Object::Array, get 1.")),
+             "Error: map expects Object::Array, get 1."),
+            ("map([1, true], fn(x) { -x });", Object::Error(String::from("Unknown operator: -true.")),
+             "Error: Unknown operator: -true."),
+            ("type(map);", Object::Str(String::from("function")), "function"),
+            ("filter([1, 2, 3, 4], fn(x) { x > 2 });", Object::Array(vec!(
+                Box::new(Object::Int(3)),
+                Box::new(Object::Int(4)),
+            )), "[3, 4]"),
+            ("filter([0, null, false, \"\"], fn(x) { x });", Object::Array(vec!(
+                Box::new(Object::Int(0)),
+                Box::new(Object::Str(String::new())),
+            )), "[0, ]"),
+            ("filter([1], 1);", Object::Error(String::from("filter expects Object::Function, get 1.")),
+             "Error: filter expects Object::Function, get 1."),
+            ("reduce([1, 2, 3], 0, fn(a, b) { a + b });", Object::Int(6), "6"),
+            ("reduce([], 10, fn(a, b) { a + b });", Object::Int(10), "10"),
+            ("reduce([\"a\", \"b\"], \"\", fn(a, b) { b + a });", Object::Str(String::from("ba")), "ba"),
+            ("reduce([1], 0, 1);", Object::Error(String::from("reduce expects Object::Function, get 1.")),
+             "Error: reduce expects Object::Function, get 1."),
+            ("reduce(1, 0, fn(a, b) { a });", Object::Error(String::from("reduce expects Object::Array, get 1.")),
+             "Error: reduce expects Object::Array, get 1."),
+            ("reduce([1], fn(a, b) { a });", Object::Error(String::from("reduce expects 3 arguments, get 2.")),
+             "Error: reduce expects 3 arguments, get 2."),
         ];
         for (input, expected, display) in test_array.iter() {
             let env = Environment::new();
             let lexer = Lexer::new(input);
             let parser = Parser::new(lexer);
-            let evaluator = Evaluator::new(parser, env);
+            let evaluator = Evaluator::new(parser, env).unwrap();
             let output: Vec<_> = evaluator.collect();
             let obj = &output[output.len()-1].0;
             println!("Evaluator: {:?} - {:?}", input, obj);
@@ -311,4 +1011,46 @@ mod tests {
             assert_eq!(display, &format!("{}", obj));
         }
     }
+
+    #[test]
+    fn evaluator_result() {
+        let test_array = [
+            ("", None),
+            ("let a = 1;", None),
+            ("let a = 1; a;", Some(Object::Int(1))),
+            ("let a = 1; a + 1;", Some(Object::Int(2))),
+            ("let a = 1; a + 1; let b = a;", Some(Object::Int(2))),
+            ("1; let a = 2;", Some(Object::Int(1))),
+            ("let a = 1; a = 2;", None),
+            ("if (false) { 1 };", Some(Object::Null)),
+            ("return 5; 6;", Some(Object::Int(5))),
+            ("let a = b; 1;", Some(Object::Error(String::from("Identifier b not found.")))),
+        ];
+        for (input, expected) in test_array.iter() {
+            let lexer = Lexer::new(input);
+            let parser = Parser::new(lexer);
+            let evaluator = Evaluator::new(parser, Environment::new()).unwrap();
+            let (result, _env) = evaluator.result();
+            println!("Evaluator: {:?} - {:?}", input, result);
+            assert_eq!(&result, expected);
+        }
+    }
+
+    #[test]
+    fn evaluator_eval() {
+        let test_array = [
+            ("let a = 1; a + 2;", Ok(Object::Int(3))),
+            ("let a = 1;", Ok(Object::Null)),
+            ("1; return 2; 3;", Ok(Object::Int(2))),
+            ("1(); 2;", Err(String::from("Error: Not a function: 1."))),
+            ("let = 1; let b 2;", Err(String::from(
+                "Parse error at 1:5: Expect Token::Ident, get Assign(\"=\").\n\
+                 Parse error at 1:16: Expect Token::Assign, get Int(\"2\")."))),
+        ];
+        for (input, expected) in test_array.iter() {
+            let result = eval(input);
+            println!("Evaluator: {:?} - {:?}", input, result);
+            assert_eq!(&result, expected);
+        }
+    }
 }
@@ -3,16 +3,32 @@ use crate::token::Token;
 pub struct Lexer {
     input: Vec<char>,
     pos: usize,
+    line: usize,
+    col: usize,
+    eof: bool,    // whether the EOF token has been emitted
 }
 
 impl Lexer {
     pub fn new(input: &str) -> Lexer{
+        // Skip the byte order mark some editors put at the start of UTF-8 files.
+        let input = input.strip_prefix('\u{feff}').unwrap_or(input);
         Lexer {
             input: input.chars().collect(),
             pos: 0,
+            line: 1,
+            col: 1,
+            eof: false,
         }
     }
 
+    // Collect the tokens without their positions, dropping EOF.
+    // Parser::new drops EOF the same way, but keeps the positions for error messages.
+    pub fn tokens(self) -> Vec<Token> {
+        self.map(|(token, _, _)| token)
+            .filter(|token| !matches!(token, Token::EOF(_)))
+            .collect()
+    }
+
     fn ch(&self) -> Option<char> {
         if self.pos < self.input.len() {
             Some(self.input[self.pos])
@@ -29,57 +45,101 @@ impl Lexer {
         }
     }
 
-    fn forward(&mut self) -> () {
+    fn forward(&mut self) {
+        if let Some('\n') = self.ch() {
+            self.line += 1;
+            self.col = 1;
+        } else {
+            self.col += 1;
+        }
         self.pos += 1;
     }
 
-    fn backward(&mut self) -> () {
+    fn backward(&mut self) {
+        // Only used to step back onto the last char of a word, so no newline is crossed.
         self.pos -= 1;
+        self.col -= 1;
+    }
+
+    fn skip_block_comment(&mut self) -> bool {
+        // Skip a comment starting at the current "/*", stopping after its "*/".
+        // Return false if EOF is reached before the comment is closed.
+        self.forward();
+        self.forward();
+        loop {
+            match (self.ch(), self.next_ch()) {
+                (Some('*'), Some('/')) => {
+                    self.forward();
+                    self.forward();
+                    return true;
+                },
+                (Some(_), _) => self.forward(),
+                (None, _) => return false,
+            }
+        }
+    }
+
+    fn read_string(&mut self) -> Token {
+        // Read Str between the opening and the closing quotes, stopping on the closing one.
+        let mut s = String::new();
+        loop {
+            self.forward();
+            match self.ch() {
+                Some('"') => return Token::Str(s),
+                Some(ch) => s.push(ch),
+                None => return Token::Illegal(String::from("Unterminated string.")),
+            }
+        }
     }
 
     fn read_word(&mut self, ch: char) -> Token {
         let mut s = String::new();
-        if ch.is_ascii_digit() {
-            // Read Int.
-            loop {
-                match self.ch() {
-                    Some(ch) => {
-                        if ch.is_ascii_digit() {
-                            s.push(ch);
-                        } else {
-                            self.backward();
-                            return Token::Int(s);
+        if ch.is_ascii_digit() || (ch == '.' && self.next_ch().is_some_and(|next| next.is_ascii_digit())) {
+            // Read Int and Float, where a single dot is allowed before an optional exponent
+            // and a single sign is allowed right after the "e" or "E".
+            let (mut dot, mut exponent, mut exponent_digits, mut valid) = (false, false, false, true);
+            while let Some(ch) = self.ch() {
+                match (ch, self.next_ch()) {
+                    (ch, _) if ch.is_ascii_digit() => {
+                        exponent_digits = exponent;
+                        s.push(ch);
+                    },
+                    ('.', Some(next)) if next.is_ascii_digit() => {
+                        valid = valid && !dot && !exponent;
+                        dot = true;
+                        s.push('.');
+                    },
+                    ('e', next) | ('E', next) => {
+                        valid = valid && !exponent;
+                        exponent = true;
+                        s.push(ch);
+                        if let Some(sign @ '+') | Some(sign @ '-') = next {
+                            self.forward();
+                            s.push(sign);
                         }
                     },
-                    None => panic!("Encounter EOF while Lexing!"),
+                    _ => break,
                 }
                 self.forward();
             }
-        } else if ch == '"' {
-            // Read Str.
-            s = String::new();
-            loop {
-                self.forward();
-                match self.ch() {
-                    Some('"') => {
-                        return Token::Str(s);
-                    },
-                    Some(ch) => s.push(ch),
-                    None => panic!("Encounter EOF while Lexing!"),
-                }
+            self.backward();
+            if !valid || (exponent && !exponent_digits) {
+                Token::Illegal(format!("Invalid number {}.", s))
+            } else if dot || exponent {
+                Token::Float(s)
+            } else {
+                Token::Int(s)
             }
         } else {
-            // Read Ident and keywords.
-            loop {
-                match self.ch() {
-                    Some(ch) => {
-                        if ch.is_ascii_alphanumeric() || ch == '_' {
-                            s.push(ch);
-                        } else {
-                            break;
-                        }
-                    },
-                    None => break,
+            if !ch.is_alphabetic() && ch != '_' {
+                return Token::Illegal(format!("Invalid character {}.", ch));
+            }
+            // Read Ident and keywords, which may contain non-ASCII letters.
+            while let Some(ch) = self.ch() {
+                if ch.is_alphanumeric() || ch == '_' {
+                    s.push(ch);
+                } else {
+                    break;
                 }
                 self.forward();
             }
@@ -91,27 +151,47 @@ impl Lexer {
                 "else" => Token::Else(s),
                 "true" => Token::True(s),
                 "false" => Token::False(s),
+                "null" => Token::Null(s),
                 "return" => Token::Return(s),
+                "while" => Token::While(s),
+                "do" => Token::Do(s),
+                "for" => Token::For(s),
                 _ => Token::Ident(s),
             }
         }
     }
 }
 
+// Count the brackets left open by the input, so the REPL knows when to keep reading.
+pub fn bracket_depth(input: &str) -> i32 {
+    Lexer::new(input).tokens().iter().fold(0, |depth, token| match token {
+        Token::Lparen(_) | Token::Lbrace(_) | Token::Lbracket(_) => depth + 1,
+        Token::Rparen(_) | Token::Rbrace(_) | Token::Rbracket(_) => depth - 1,
+        _ => depth,
+    })
+}
+
 impl Iterator for Lexer {
 
-    type Item = Token;
+    // Token with its line and column.
+    type Item = (Token, usize, usize);
 
     fn next(&mut self) -> Option<Self::Item> {
-        // Skip whitespaces.
+        // Skip whitespaces and comments.
         loop {
-            let ch = self.ch();
-            if ch.is_some() && ch.unwrap().is_whitespace() {
-                self.forward();
-            } else {
-                break;
+            match (self.ch(), self.next_ch()) {
+                (Some(ch), _) if ch.is_whitespace() => self.forward(),
+                (Some('/'), Some('*')) => {
+                    let (line, col) = (self.line, self.col);
+                    if !self.skip_block_comment() {
+                        let token = Token::Illegal(String::from("Unterminated block comment."));
+                        return Some((token, line, col));
+                    }
+                },
+                _ => break,
             }
         }
+        let (line, col) = (self.line, self.col);
         // Read single-char tokens.
         let token = match self.ch() {
             Some('=') => {
@@ -132,12 +212,59 @@ impl Iterator for Lexer {
                     _ => Some(Token::Bang(String::from("!"))),
                 }
             },
+            Some('&') => {
+                match self.next_ch() {
+                    Some('&') => {
+                        self.forward();
+                        Some(Token::And(String::from("&&")))
+                    },
+                    _ => Some(Token::Ampersand(String::from("&"))),
+                }
+            },
+            Some('|') => {
+                match self.next_ch() {
+                    Some('|') => {
+                        self.forward();
+                        Some(Token::Or(String::from("||")))
+                    },
+                    _ => Some(Token::Pipe(String::from("|"))),
+                }
+            },
+            Some(ch @ '+') | Some(ch @ '-') | Some(ch @ '*') | Some(ch @ '/') if self.next_ch() == Some('=') => {
+                self.forward();
+                let op = format!("{}=", ch);
+                match ch {
+                    '+' => Some(Token::PlusAssign(op)),
+                    '-' => Some(Token::MinusAssign(op)),
+                    '*' => Some(Token::AsteriskAssign(op)),
+                    _ => Some(Token::SlashAssign(op)),
+                }
+            },
             Some('+') => Some(Token::Plus(String::from("+"))),
             Some('-') => Some(Token::Minus(String::from("-"))),
             Some('*') => Some(Token::Asterisk(String::from("*"))),
             Some('/') => Some(Token::Slash(String::from("/"))),
-            Some('<') => Some(Token::LT(String::from("<"))),
-            Some('>') => Some(Token::GT(String::from(">"))),
+            Some('%') => Some(Token::Percent(String::from("%"))),
+            Some('<') => {
+                match self.next_ch() {
+                    Some('<') => {
+                        self.forward();
+                        Some(Token::ShiftLeft(String::from("<<")))
+                    },
+                    _ => Some(Token::LT(String::from("<"))),
+                }
+            },
+            Some('>') => {
+                match self.next_ch() {
+                    Some('>') => {
+                        self.forward();
+                        Some(Token::ShiftRight(String::from(">>")))
+                    },
+                    _ => Some(Token::GT(String::from(">"))),
+                }
+            },
+            Some('^') => Some(Token::Caret(String::from("^"))),
+            Some('~') => Some(Token::Tilde(String::from("~"))),
             Some('(') => Some(Token::Lparen(String::from("("))),
             Some(')') => Some(Token::Rparen(String::from(")"))),
             Some('[') => Some(Token::Lbracket(String::from("["))),
@@ -145,13 +272,20 @@ impl Iterator for Lexer {
             Some('{') => Some(Token::Lbrace(String::from("{"))),
             Some('}') => Some(Token::Rbrace(String::from("}"))),
             Some(',') => Some(Token::Comma(String::from(","))),
+            Some(':') => Some(Token::Colon(String::from(":"))),
+            Some('?') => Some(Token::Question(String::from("?"))),
             Some(';') => Some(Token::Semicolon(String::from(";"))),
-            Some('\0') => Some(Token::EOF(String::from(""))),
-            None => None,
+            Some('"') => Some(self.read_string()),
+            // Emit a single EOF at the end of the input.
+            None if self.eof => None,
+            None => {
+                self.eof = true;
+                Some(Token::EOF(String::from("")))
+            },
             Some(ch) => Some(self.read_word(ch)),
         };
         self.forward();
-        token
+        token.map(|token| (token, line, col))
     }
 }
 
@@ -161,6 +295,7 @@ mod tests {
 
     use super::Token;
     use super::Lexer;
+    use super::bracket_depth;
     
     #[test]
     fn lexer() {
@@ -171,7 +306,8 @@ mod tests {
                 x + y;
             };
 
-            !-/*5;
+            !-/ *5;
+            5 % 3;
             5 < 10 > 5;
 
             if (5 < 10) {
@@ -182,10 +318,16 @@ mod tests {
 
             10 == 10;
             10 != 9;
+            while (x) {}
+            do {} while (x);
+            for
+            a && b || c;
 
             \"a b\";
+            \"\";
 
             [];
+            {\"a\": 1};
         ";
         let output = [
             Token::Let(String::from("let")),
@@ -218,6 +360,10 @@ mod tests {
             Token::Int(String::from("5")),
             Token::Semicolon(String::from(";")),
             Token::Int(String::from("5")),
+            Token::Percent(String::from("%")),
+            Token::Int(String::from("3")),
+            Token::Semicolon(String::from(";")),
+            Token::Int(String::from("5")),
             Token::LT(String::from("<")),
             Token::Int(String::from("10")),
             Token::GT(String::from(">")),
@@ -252,19 +398,280 @@ mod tests {
             Token::Int(String::from("9")),
             Token::Semicolon(String::from(";")),
 
+            Token::While(String::from("while")),
+            Token::Lparen(String::from("(")),
+            Token::Ident(String::from("x")),
+            Token::Rparen(String::from(")")),
+            Token::Lbrace(String::from("{")),
+            Token::Rbrace(String::from("}")),
+
+            Token::Do(String::from("do")),
+            Token::Lbrace(String::from("{")),
+            Token::Rbrace(String::from("}")),
+            Token::While(String::from("while")),
+            Token::Lparen(String::from("(")),
+            Token::Ident(String::from("x")),
+            Token::Rparen(String::from(")")),
+            Token::Semicolon(String::from(";")),
+
+            Token::For(String::from("for")),
+
+            Token::Ident(String::from("a")),
+            Token::And(String::from("&&")),
+            Token::Ident(String::from("b")),
+            Token::Or(String::from("||")),
+            Token::Ident(String::from("c")),
+            Token::Semicolon(String::from(";")),
+
             Token::Str(String::from("a b")),
             Token::Semicolon(String::from(";")),
+            Token::Str(String::from("")),
+            Token::Semicolon(String::from(";")),
 
             Token::Lbracket(String::from("[")),
             Token::Rbracket(String::from("]")),
             Token::Semicolon(String::from(";")),
 
+            Token::Lbrace(String::from("{")),
+            Token::Str(String::from("a")),
+            Token::Colon(String::from(":")),
+            Token::Int(String::from("1")),
+            Token::Rbrace(String::from("}")),
+            Token::Semicolon(String::from(";")),
+
             Token::EOF(String::from("")),
         ];
         let lexer = Lexer::new(input);
-        for (result, expected) in lexer.zip(output.iter()) {
+        let mut count = 0;
+        for ((result, _, _), expected) in lexer.zip(output.iter()) {
             println!("Lexer: {:?} - {:?}", &result, expected);
             assert_eq!(&result, expected);
+            count += 1;
+        }
+        assert_eq!(count, output.len());
+    }
+
+    #[test]
+    fn lexer_array() {
+        let input = "[1, 2][0]";
+        let output = vec!(
+            Token::Lbracket(String::from("[")),
+            Token::Int(String::from("1")),
+            Token::Comma(String::from(",")),
+            Token::Int(String::from("2")),
+            Token::Rbracket(String::from("]")),
+            Token::Lbracket(String::from("[")),
+            Token::Int(String::from("0")),
+            Token::Rbracket(String::from("]")),
+            Token::EOF(String::from("")),
+        );
+        let result: Vec<Token> = Lexer::new(input).map(|(token, _, _)| token).collect();
+        println!("Lexer: {:?} - {:?}", &result, &output);
+        assert_eq!(result, output);
+    }
+
+    #[test]
+    fn lexer_comment() {
+        let input = "
+            /* A comment
+               spanning lines. */
+            1 /* inline */ + 2;
+            /* unterminated
+        ";
+        let output = vec!(
+            Token::Int(String::from("1")),
+            Token::Plus(String::from("+")),
+            Token::Int(String::from("2")),
+            Token::Semicolon(String::from(";")),
+            Token::Illegal(String::from("Unterminated block comment.")),
+            Token::EOF(String::from("")),
+        );
+        let result: Vec<Token> = Lexer::new(input).map(|(token, _, _)| token).collect();
+        println!("Lexer: {:?} - {:?}", &result, &output);
+        assert_eq!(result, output);
+    }
+
+    #[test]
+    fn lexer_string() {
+        let input = "\"a\" + \"abc";
+        let output = vec!(
+            Token::Str(String::from("a")),
+            Token::Plus(String::from("+")),
+            Token::Illegal(String::from("Unterminated string.")),
+            Token::EOF(String::from("")),
+        );
+        let result: Vec<Token> = Lexer::new(input).map(|(token, _, _)| token).collect();
+        println!("Lexer: {:?} - {:?}", &result, &output);
+        assert_eq!(result, output);
+    }
+
+    #[test]
+    fn lexer_float() {
+        let input = "3.14; 1.2.3; 1e10; 1.5e-3; 2E+2; .5; 1e; 1e+; 1e5e3; 1e2.5; 5.;";
+        let output = vec!(
+            Token::Float(String::from("3.14")),
+            Token::Illegal(String::from("Invalid number 1.2.3.")),
+            Token::Float(String::from("1e10")),
+            Token::Float(String::from("1.5e-3")),
+            Token::Float(String::from("2E+2")),
+            Token::Float(String::from(".5")),
+            Token::Illegal(String::from("Invalid number 1e.")),
+            Token::Illegal(String::from("Invalid number 1e+.")),
+            Token::Illegal(String::from("Invalid number 1e5e3.")),
+            Token::Illegal(String::from("Invalid number 1e2.5.")),
+            Token::Int(String::from("5")),
+            Token::Illegal(String::from("Invalid character ..")),
+            Token::EOF(String::from("")),
+        );
+        let result: Vec<Token> = Lexer::new(input)
+            .map(|(token, _, _)| token)
+            .filter(|token| !matches!(token, Token::Semicolon(_)))
+            .collect();
+        println!("Lexer: {:?} - {:?}", &result, &output);
+        assert_eq!(result, output);
+    }
+
+    #[test]
+    fn lexer_unicode() {
+        let test_array = [
+            ("let π = 3.14;", vec!(
+                Token::Let(String::from("let")),
+                Token::Ident(String::from("π")),
+                Token::Assign(String::from("=")),
+                Token::Float(String::from("3.14")),
+                Token::Semicolon(String::from(";")),
+            )),
+            ("größe_1+名前", vec!(
+                Token::Ident(String::from("größe_1")),
+                Token::Plus(String::from("+")),
+                Token::Ident(String::from("名前")),
+            )),
+            ("\u{feff}let a", vec!(
+                Token::Let(String::from("let")),
+                Token::Ident(String::from("a")),
+            )),
+            ("a\u{feff}", vec!(
+                Token::Ident(String::from("a")),
+                Token::Illegal(String::from("Invalid character \u{feff}.")),
+            )),
+            ("→", vec!(Token::Illegal(String::from("Invalid character →.")))),
+        ];
+        for (input, output) in test_array.iter() {
+            let result = Lexer::new(input).tokens();
+            println!("Lexer: {:?} - {:?}", &result, output);
+            assert_eq!(&result, output);
+        }
+        // Positions are counted in chars, after the byte order mark.
+        let result: Vec<_> = Lexer::new("\u{feff}é b").collect();
+        assert_eq!(result, vec!(
+            (Token::Ident(String::from("é")), 1, 1),
+            (Token::Ident(String::from("b")), 1, 3),
+            (Token::EOF(String::from("")), 1, 4),
+        ));
+    }
+
+    #[test]
+    fn lexer_position() {
+        let input = "let a = 10;\n  \"s\" b\n\n/* c\n */ fn";
+        let output = vec!(
+            (Token::Let(String::from("let")), 1, 1),
+            (Token::Ident(String::from("a")), 1, 5),
+            (Token::Assign(String::from("=")), 1, 7),
+            (Token::Int(String::from("10")), 1, 9),
+            (Token::Semicolon(String::from(";")), 1, 11),
+            (Token::Str(String::from("s")), 2, 3),
+            (Token::Ident(String::from("b")), 2, 7),
+            (Token::Function(String::from("fn")), 5, 5),
+            (Token::EOF(String::from("")), 5, 7),
+        );
+        let result: Vec<_> = Lexer::new(input).collect();
+        println!("Lexer: {:?} - {:?}", &result, &output);
+        assert_eq!(result, output);
+    }
+
+    #[test]
+    fn lexer_bitwise() {
+        let input = "a & b | c ^ d << 1 >> 2 && e || f < g > ~h";
+        let output = vec!(
+            Token::Ident(String::from("a")),
+            Token::Ampersand(String::from("&")),
+            Token::Ident(String::from("b")),
+            Token::Pipe(String::from("|")),
+            Token::Ident(String::from("c")),
+            Token::Caret(String::from("^")),
+            Token::Ident(String::from("d")),
+            Token::ShiftLeft(String::from("<<")),
+            Token::Int(String::from("1")),
+            Token::ShiftRight(String::from(">>")),
+            Token::Int(String::from("2")),
+            Token::And(String::from("&&")),
+            Token::Ident(String::from("e")),
+            Token::Or(String::from("||")),
+            Token::Ident(String::from("f")),
+            Token::LT(String::from("<")),
+            Token::Ident(String::from("g")),
+            Token::GT(String::from(">")),
+            Token::Tilde(String::from("~")),
+            Token::Ident(String::from("h")),
+            Token::EOF(String::from("")),
+        );
+        let result: Vec<Token> = Lexer::new(input).map(|(token, _, _)| token).collect();
+        println!("Lexer: {:?} - {:?}", &result, &output);
+        assert_eq!(result, output);
+    }
+
+    #[test]
+    fn lexer_compound_assign() {
+        let input = "x += 1; x -= 2; x *= 3; x /= 4; x = - 5;";
+        let output: Vec<Token> = vec!(
+            Token::PlusAssign(String::from("+=")),
+            Token::MinusAssign(String::from("-=")),
+            Token::AsteriskAssign(String::from("*=")),
+            Token::SlashAssign(String::from("/=")),
+            Token::Assign(String::from("=")),
+            Token::Minus(String::from("-")),
+            Token::EOF(String::from("")),
+        );
+        let result: Vec<Token> = Lexer::new(input)
+            .map(|(token, _, _)| token)
+            .filter(|token| !matches!(token, Token::Ident(_) | Token::Int(_) | Token::Semicolon(_)))
+            .collect();
+        println!("Lexer: {:?} - {:?}", &result, &output);
+        assert_eq!(result, output);
+    }
+
+    #[test]
+    fn lexer_tokens() {
+        let test_array = [
+            ("5;", vec!(Token::Int(String::from("5")), Token::Semicolon(String::from(";")))),
+            ("5;\n", vec!(Token::Int(String::from("5")), Token::Semicolon(String::from(";")))),
+            ("", vec!()),
+            ("null;", vec!(Token::Null(String::from("null")), Token::Semicolon(String::from(";")))),
+            ("nullable", vec!(Token::Ident(String::from("nullable")))),
+            ("@\0", vec!(
+                Token::Illegal(String::from("Invalid character @.")),
+                Token::Illegal(String::from("Invalid character \0.")),
+            )),
+        ];
+        for (input, output) in test_array.iter() {
+            let result = Lexer::new(input).tokens();
+            assert!(!result.iter().any(|token| matches!(token, Token::EOF(_))));
+            assert_eq!(&result, output);
+        }
+    }
+
+    #[test]
+    fn lexer_bracket_depth() {
+        let test_array = [
+            ("let a = 1;", 0),
+            ("let f = fn(x) {", 1),
+            ("let f = fn(x) {\n  [x, {\"a\": (1", 4),
+            ("let f = fn(x) {\n  x\n};", 0),
+            ("\"{(\" /* [ */", 0),
+            ("}", -1),
+        ];
+        for (input, depth) in test_array.iter() {
+            assert_eq!(bracket_depth(input), *depth, "{:?}", input);
         }
     }
 }
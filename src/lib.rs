@@ -0,0 +1,16 @@
+#![allow(clippy::vec_box, clippy::upper_case_acronyms)]
+
+pub mod token;
+pub mod lexer;
+pub mod ast;
+pub mod parser;
+pub mod object;
+pub mod builtins;
+pub mod evaluator;
+
+pub mod code;
+pub mod compiler;
+pub mod vm;
+
+pub use evaluator::eval;
+pub use vm::vm_eval;
@@ -1,63 +1,307 @@
-mod token;
-mod lexer;
-mod ast;
-mod parser;
-mod object;
-mod evaluator;
-
-mod code;
-mod compiler;
-mod vm;
-
-use lexer::Lexer;
-use parser::Parser;
-use evaluator::Evaluator;
-use object::Environment;
-use compiler::Compiler;
-use code::SymbolTable;
-use vm::VM;
+use monkey::lexer::{Lexer, bracket_depth};
+use monkey::parser::Parser;
+use monkey::evaluator::Evaluator;
+use monkey::object::Environment;
+use monkey::compiler::Compiler;
+use monkey::code::{Code, Scope, SymbolTable, disassemble, serialize, deserialize, MAGIC};
+use monkey::vm::{VM, repl_value};
+use monkey::object::Object;
 use std::io;
 use std::io::Write;
 use std::env;
+use std::mem;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::Instant;
 use std::collections::HashMap;
 
 fn main() {
     let args: Vec<String> = env::args().collect();
-    let vm_flag = if args.len() > 1 && args[1].as_str() == "vm" {
-        true
-    } else {
-        false
-    };
+    let vm_flag = args.len() > 1 && args[1].as_str() == "vm";
+    let rest = if vm_flag { &args[2..] } else { &args[1..] };
+    if !rest.is_empty() && rest[0].as_str() == "run" {
+        process::exit(run_file(rest.get(1), vm_flag));
+    }
+    if !rest.is_empty() && rest[0].as_str() == "compile" {
+        process::exit(compile_file(&rest[1..]));
+    }
     println!("Welcome to the Monkey Programming Language in Rust! ({})",
              if vm_flag { "VM" } else { "Interpreter" });
-    let mut environment = Environment::new();
-    let mut symbol_table = SymbolTable::new(None);
-    let mut globals = HashMap::new();
+    let mut session = Session::new(vm_flag);
+    let mut input = String::new();
     loop {
-        print!(">> ");
+        print!("{}", if input.is_empty() { ">> " } else { ".. " });
         io::stdout().flush().unwrap();
-        let mut input = String::new();
         match io::stdin().read_line(&mut input) {
+            Ok(0) if input.is_empty() => {
+                println!();
+                return;
+            },
+            Ok(0) => {
+                // Drop the unfinished input on EOF.
+                println!();
+                input.clear();
+            },
+            Ok(_) if bracket_depth(&input) > 0 => continue,
             Ok(_) => {
-                let lexer = Lexer::new(&input);
-                let parser = Parser::new(lexer);
-                if vm_flag {
-                    let compiler = Compiler::new(parser, symbol_table);
-                    let (code, sym_table) = compiler.run();
-                    let vm = VM::new(code, globals);
-                    let (result, _popped, gb) = vm.run();
-                    println!("{}", result);
-                    symbol_table = sym_table;
-                    globals = gb;
+                let input = mem::take(&mut input);
+                if input.starts_with(':') {
+                    session.run_command(&input);
                 } else {
-                    let evaluator = Evaluator::new(parser, environment.clone());
-                    for (obj, env) in evaluator {
-                        println!("{}", obj);
-                        environment = env;
-                    }
+                    session.eval(&input);
                 }
             }
             Err(_) => continue,
         }
     }
 }
+
+// State of the REPL carried from one line to the next.
+struct Session {
+    vm_flag: bool,
+    environment: Environment,
+    symbol_table: SymbolTable,
+    globals: HashMap<usize, Object>,
+    constants: Vec<Object>,
+}
+
+impl Session {
+    fn new(vm_flag: bool) -> Session {
+        Session {
+            vm_flag,
+            environment: Environment::new(),
+            symbol_table: SymbolTable::new(None),
+            globals: HashMap::new(),
+            constants: vec!(),
+        }
+    }
+
+    fn eval(&mut self, input: &str) {
+        let lexer = Lexer::new(input);
+        let parser = Parser::new(lexer);
+        if self.vm_flag {
+            let compiler = match Compiler::new(parser, self.symbol_table.clone(), self.constants.clone()) {
+                Ok(compiler) => compiler,
+                Err(errors) => {
+                    for err in errors.iter() {
+                        println!("{}", err);
+                    }
+                    return;
+                },
+            };
+            let (code, symbol_table, constants) = match compiler.run() {
+                Ok(compiled) => compiled,
+                Err(err) => {
+                    println!("{}", err);
+                    return;
+                },
+            };
+            // A failed line leaves the session as it was before the line.
+            let vm = VM::new(code, constants.clone(), self.globals.clone());
+            let (result, popped, globals) = match vm.run() {
+                Ok(output) => output,
+                Err(err) => {
+                    println!("{}", err);
+                    return;
+                },
+            };
+            if let Some(obj) = repl_value(result, popped) {
+                println!("{}", obj);
+            }
+            self.symbol_table = symbol_table;
+            self.constants = constants;
+            self.globals = globals;
+        } else {
+            let evaluator = match Evaluator::new(parser, self.environment.clone()) {
+                Ok(evaluator) => evaluator,
+                Err(errors) => {
+                    for err in errors.iter() {
+                        println!("{}", err);
+                    }
+                    return;
+                },
+            };
+            let (result, environment) = evaluator.result();
+            if let Some(obj) = result {
+                println!("{}", obj);
+            }
+            self.environment = environment;
+        }
+    }
+
+    fn run_command(&mut self, input: &str) {
+        let (command, argument) = match input.trim().find(char::is_whitespace) {
+            Some(index) => input.trim().split_at(index),
+            None => (input.trim(), ""),
+        };
+        match command {
+            ":bytecode" => {
+                let lexer = Lexer::new(argument);
+                let parser = Parser::new(lexer);
+                match Compiler::new(parser, self.symbol_table.clone(), self.constants.clone()) {
+                    Ok(compiler) => match compiler.run() {
+                        Ok((code, _, constants)) => print!("{}", disassemble(&code, &constants)),
+                        Err(err) => println!("{}", err),
+                    },
+                    Err(errors) => {
+                        for err in errors.iter() {
+                            println!("{}", err);
+                        }
+                    },
+                }
+            },
+            ":env" => {
+                let bindings = if self.vm_flag {
+                    let mut bindings: Vec<(String, Object)> = self.symbol_table.map.values()
+                        .filter(|sym| sym.scope == Scope::Global)
+                        .filter_map(|sym| self.globals.get(&sym.index).map(|obj| (sym.name.clone(), obj.clone())))
+                        .collect();
+                    bindings.sort_by(|(a, _), (b, _)| a.cmp(b));
+                    bindings
+                } else {
+                    self.environment.get_all()
+                };
+                for (name, obj) in bindings.iter() {
+                    println!("{} = {}", name, obj);
+                }
+            },
+            ":time" => {
+                let start = Instant::now();
+                self.eval(argument);
+                println!("Time: {} us", start.elapsed().as_micros());
+            },
+            ":reset" => {
+                *self = Session::new(self.vm_flag);
+                println!("All bindings are cleared.");
+            },
+            _ => println!("Unknown command {}.", command),
+        }
+    }
+}
+
+fn run_file(path: Option<&String>, vm_flag: bool) -> i32 {
+    let path = match path {
+        Some(path) => path,
+        None => {
+            eprintln!("Usage: monkey [vm] run <file>");
+            return 1;
+        },
+    };
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            eprintln!("Cannot read {}: {}", path, err);
+            return 1;
+        },
+    };
+    let result = if bytes.starts_with(MAGIC) {
+        // Compiled bytecode always runs in the VM.
+        match deserialize(&bytes) {
+            Ok((code, constants)) => run_vm(code, constants),
+            Err(err) => {
+                eprintln!("Cannot load {}: {}", path, err);
+                return 1;
+            },
+        }
+    } else {
+        let input = match String::from_utf8(bytes) {
+            Ok(input) => input,
+            Err(_) => {
+                eprintln!("Cannot read {}: invalid UTF-8.", path);
+                return 1;
+            },
+        };
+        if vm_flag {
+            match compile_source(&input) {
+                Some((code, constants)) => run_vm(code, constants),
+                None => return 1,
+            }
+        } else {
+            let lexer = Lexer::new(&input);
+            let parser = Parser::new(lexer);
+            let evaluator = match Evaluator::new(parser, Environment::new()) {
+                Ok(evaluator) => evaluator,
+                Err(errors) => {
+                    for err in errors.iter() {
+                        eprintln!("{}", err);
+                    }
+                    return 1;
+                },
+            };
+            // Show the last expression value like the VM does, not the value of a trailing let.
+            let (result, _env) = evaluator.result();
+            result.unwrap_or(Object::Null)
+        }
+    };
+    if let Object::Error(_) = result {
+        eprintln!("{}", result);
+        1
+    } else {
+        println!("{}", result);
+        0
+    }
+}
+
+fn compile_file(args: &[String]) -> i32 {
+    let (path, output) = match args {
+        [path] => (path, Path::new(path).with_extension("mbc")),
+        [path, flag, output] if flag.as_str() == "-o" => (path, PathBuf::from(output)),
+        _ => {
+            eprintln!("Usage: monkey compile <file> [-o <output>]");
+            return 1;
+        },
+    };
+    let input = match fs::read_to_string(path) {
+        Ok(input) => input,
+        Err(err) => {
+            eprintln!("Cannot read {}: {}", path, err);
+            return 1;
+        },
+    };
+    let (code, constants) = match compile_source(&input) {
+        Some(compiled) => compiled,
+        None => return 1,
+    };
+    let bytes = match serialize(&code, &constants) {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            eprintln!("{}", err);
+            return 1;
+        },
+    };
+    match fs::write(&output, bytes) {
+        Ok(()) => 0,
+        Err(err) => {
+            eprintln!("Cannot write {}: {}", output.display(), err);
+            1
+        },
+    }
+}
+
+fn compile_source(input: &str) -> Option<(Vec<Code>, Vec<Object>)> {
+    let lexer = Lexer::new(input);
+    let parser = Parser::new(lexer);
+    match Compiler::new(parser, SymbolTable::new(None), vec!()) {
+        Ok(compiler) => match compiler.compile_program() {
+            Ok(bytecode) => Some((bytecode.instructions, bytecode.constants)),
+            Err(err) => {
+                eprintln!("{}", err);
+                None
+            },
+        },
+        Err(errors) => {
+            for err in errors.iter() {
+                eprintln!("{}", err);
+            }
+            None
+        },
+    }
+}
+
+fn run_vm(code: Vec<Code>, constants: Vec<Object>) -> Object {
+    match VM::new(code, constants, HashMap::new()).run() {
+        Ok((result, popped, _globals)) => repl_value(result, popped).unwrap_or(Object::Null),
+        Err(err) => Object::Error(err.message),
+    }
+}
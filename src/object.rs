@@ -1,90 +1,345 @@
+use std::cell::RefCell;
 use std::collections::HashMap;
 use std::fmt;
+use std::rc::Rc;
 
 use crate::ast::Expression;
 use crate::ast::Statement;
 use crate::code::Code;
 
-#[derive(PartialEq, Eq, Debug, Clone)]
+pub const MAX_STR_LEN: usize = 1 << 28;    // bytes a string may grow to by repetition
+pub const MAX_ARRAY_LEN: usize = 1 << 24;    // elements an array may be built with by range
+
+// Only PartialEq is derived, since floats follow IEEE 754: NaN != NaN and 0.0 == -0.0.
+// The comparison is structural, so Int(1) != Float(1.0), unlike `1 == 1.0` in Monkey.
+#[derive(PartialEq, Debug, Clone)]
 pub enum Object {
-    Int(i32),
+    Int(i64),    // 64 bits, so factorials and fibonacci numbers go far before overflowing
+    Float(f64),
     Str(String),
     Bool(bool),
     Null,
     Return(Box<Object>),
+    Error(String),
     Array(Vec<Box<Object>>),
+    Hash(HashMap<HashKey, Object>),
     Function {
         parameters: Vec<Box<Expression>>,
         body: Box<Statement>,
         env: Environment,
     },
+    Builtin(String),    // name of the builtin function
     CompiledFunction {
-        instructions: Vec<Code>,
+        instructions: Rc<Vec<Code>>,    // shared by every call of the function
         num_locals: usize,
         num_paras: usize,
     },
 }
 
+#[derive(PartialEq, Eq, Hash, Debug, Clone)]
+pub enum HashKey {
+    Int(i64),
+    Str(String),
+    Bool(bool),
+}
+
+impl HashKey {
+    pub fn object(&self) -> Object {
+        match self {
+            HashKey::Int(v) => Object::Int(*v),
+            HashKey::Str(s) => Object::Str(s.clone()),
+            HashKey::Bool(v) => Object::Bool(*v),
+        }
+    }
+}
+
+impl Object {
+    pub fn hashable(&self) -> Option<HashKey> {
+        match self {
+            Object::Int(v) => Some(HashKey::Int(*v)),
+            Object::Str(s) => Some(HashKey::Str(s.clone())),
+            Object::Bool(v) => Some(HashKey::Bool(*v)),
+            _ => None,
+        }
+    }
+}
+
 impl fmt::Display for Object {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             Object::Int(v) => write!(f, "{}", v),
+            Object::Float(v) => write!(f, "{:?}", v),
             Object::Str(s) => write!(f, "{}", s),
             Object::Bool(v) => write!(f, "{}", v),
             Object::Null => write!(f, "Null"),
             Object::Return(obj) => write!(f, "{}", *obj),
+            Object::Error(msg) => write!(f, "Error: {}", msg),
             Object::Array(vec) => {
                 let mut s = String::from("[");
                 for obj in vec.iter() {
                     s += format!("{}, ", obj).as_str();
                 }
-                s.pop();
-                s.pop();
+                if !vec.is_empty() {
+                    s.pop();
+                    s.pop();
+                }
                 s += "]";
                 write!(f, "{}", s)
-            }
+            },
+            Object::Hash(hash) => {
+                // Sort the pairs so the output does not depend on the iteration order.
+                let mut pairs: Vec<String> = hash.iter()
+                    .map(|(key, value)| format!("{}: {}", key.object(), value))
+                    .collect();
+                pairs.sort();
+                let mut s = String::from("{");
+                for pair in pairs.iter() {
+                    s += format!("{}, ", pair).as_str();
+                }
+                if !pairs.is_empty() {
+                    s.pop();
+                    s.pop();
+                }
+                s += "}";
+                write!(f, "{}", s)
+            },
             Object::Function {
-                parameters: _,
+                parameters,
                 body: _,
                 env: _,
-            } => write!(f, "function"),
+            } => {
+                let names: Vec<String> = parameters.iter().map(|para| match para.as_ref() {
+                    Expression::Ident(name) => name.clone(),
+                    other => format!("{:?}", other),
+                }).collect();
+                write!(f, "fn({}) {{ ... }}", names.join(", "))
+            },
+            Object::Builtin(name) => write!(f, "builtin function {}", name),
             Object::CompiledFunction { instructions: _, num_locals: _, num_paras: _ } => write!(f, "compiled function"),
         }
     }
 }
 
-#[derive(PartialEq, Eq, Debug, Clone)]
+// Resolve an array index, where a negative index counts from the end.
+pub fn array_index(index: i64, len: usize) -> Option<usize> {
+    let index = if index < 0 { index + len as i64 } else { index };
+    if 0 <= index && index < len as i64 {
+        Some(index as usize)
+    } else {
+        None
+    }
+}
+
+// Repeat a string for `str * int`, where a negative count repeats it zero times.
+// None means the result would be longer than MAX_STR_LEN bytes.
+pub fn repeat_str(s: &str, n: i64) -> Option<String> {
+    let n = n.max(0) as usize;
+    match s.len().checked_mul(n) {
+        Some(len) if len <= MAX_STR_LEN => Some(s.repeat(n)),
+        _ => None,
+    }
+}
+
+// Clamp the bounds of a slice to the array, counting negative bounds from the end like
+// indexes do. The end never falls before the start, so the slice may be empty.
+pub fn slice_range(start: i64, end: i64, len: usize) -> (usize, usize) {
+    let clamp = |bound: i64| {
+        let bound = if bound < 0 { bound + len as i64 } else { bound };
+        bound.clamp(0, len as i64) as usize
+    };
+    let (start, end) = (clamp(start), clamp(end));
+    (start, end.max(start))
+}
+
+// Promote a pair of numbers to floats when at least one of them is a float.
+pub fn float_operands(left: &Object, right: &Object) -> Option<(f64, f64)> {
+    match (left, right) {
+        (&Object::Float(l), &Object::Float(r)) => Some((l, r)),
+        (&Object::Int(l), &Object::Float(r)) => Some((l as f64, r)),
+        (&Object::Float(l), &Object::Int(r)) => Some((l, r as f64)),
+        _ => None,
+    }
+}
+
+// Only null and false are falsy, every other value (including 0, "" and []) is truthy.
+pub fn is_truthy(obj: &Object) -> bool {
+    !matches!(obj, Object::Null | Object::Bool(false))
+}
+
+// Clones share the same bindings, so closures see later assignments.
+#[derive(Clone)]
 pub struct Environment {
-    env: HashMap<String, Object>,
-    outer: Option<Box<Environment>>,
+    env: Rc<RefCell<HashMap<String, Object>>>,
+    outer: Option<Rc<Environment>>,
 }
 
 impl Environment {
     pub fn new() -> Environment {
         Environment {
-            env: HashMap::new(),
+            env: Rc::new(RefCell::new(HashMap::new())),
             outer: None,
         }
     }
 
     pub fn init(outer: Environment) -> Environment {
         Environment {
-            env: HashMap::new(),
-            outer: Some(Box::new(outer)),
+            env: Rc::new(RefCell::new(HashMap::new())),
+            outer: Some(Rc::new(outer)),
         }
     }
 
     pub fn get(&self, key: &String) -> Option<Object> {
-        match self.env.get(key) {
+        match self.env.borrow().get(key) {
             Some(value) => Some(value.clone()),
             None => match &self.outer {
                 Some(e) => e.get(key),
-                None => panic!("Identifier {} not found.", key),
+                None => None,
             },
         }
     }
 
-    pub fn set(&mut self, key: String, value: Object) -> () {
-        self.env.insert(key, value);
+    pub fn get_all(&self) -> Vec<(String, Object)> {
+        // Collect every visible binding sorted by name, inner bindings shadow outer ones.
+        let mut all: HashMap<String, Object> = match &self.outer {
+            Some(e) => e.get_all().into_iter().collect(),
+            None => HashMap::new(),
+        };
+        for (key, value) in self.env.borrow().iter() {
+            all.insert(key.clone(), value.clone());
+        }
+        let mut all: Vec<(String, Object)> = all.into_iter().collect();
+        all.sort_by(|(a, _), (b, _)| a.cmp(b));
+        all
+    }
+
+    pub fn set(&self, key: String, value: Object) {
+        self.env.borrow_mut().insert(key, value);
+    }
+
+    pub fn reassign(&self, key: String, value: Object) -> Result<(), String> {
+        // Update an existing binding in the innermost environment defining it.
+        if let Some(v) = self.env.borrow_mut().get_mut(&key) {
+            *v = value;
+            return Ok(());
+        }
+        match &self.outer {
+            Some(e) => e.reassign(key, value),
+            None => Err(format!("Identifier {} not found.", key)),
+        }
+    }
+}
+
+impl Default for Environment {
+    fn default() -> Environment {
+        Environment::new()
+    }
+}
+
+impl PartialEq for Environment {
+    fn eq(&self, other: &Environment) -> bool {
+        // Check identity first, a function stored in its own environment would recurse forever.
+        (Rc::ptr_eq(&self.env, &other.env) || self.env == other.env) && self.outer == other.outer
+    }
+}
+
+impl fmt::Debug for Environment {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut names: Vec<String> = self.env.borrow().keys().cloned().collect();
+        names.sort();
+        write!(f, "Environment {:?}", names)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn object_hashable() {
+        let test_array = [
+            (Object::Int(1), Object::Int(1), true),
+            (Object::Str(String::from("a")), Object::Str(String::from("a")), true),
+            (Object::Bool(true), Object::Bool(true), true),
+            (Object::Int(1), Object::Bool(true), false),
+            (Object::Int(1), Object::Str(String::from("1")), false),
+        ];
+        for (a, b, same) in test_array.iter() {
+            let mut hash = HashMap::new();
+            hash.insert(a.hashable().unwrap(), Object::Int(1));
+            hash.insert(b.hashable().unwrap(), Object::Int(2));
+            assert_eq!(hash.len() == 1, *same, "{:?} - {:?}", a, b);
+            assert_eq!(a.hashable().unwrap().object(), *a);
+        }
+        assert_eq!(Object::Array(Vec::new()).hashable(), None);
+        assert_eq!(Object::Float(1.0).hashable(), None);
+        assert_eq!(Object::Null.hashable(), None);
+    }
+
+    #[test]
+    fn object_equality() {
+        let array = |vec: Vec<Object>| Object::Array(vec.into_iter().map(Box::new).collect());
+        let test_array = [
+            (Object::Float(1.5), Object::Float(1.5), true),
+            (Object::Float(0.0), Object::Float(-0.0), true),
+            (Object::Float(f64::NAN), Object::Float(f64::NAN), false),
+            (Object::Int(1), Object::Float(1.0), false),
+            (array(vec!(Object::Float(1.5), Object::Int(1))), array(vec!(Object::Float(1.5), Object::Int(1))), true),
+            (array(vec!(Object::Float(f64::NAN))), array(vec!(Object::Float(f64::NAN))), false),
+            (array(vec!(Object::Float(1.0))), array(vec!(Object::Int(1))), false),
+            (Object::Hash(HashMap::from([(HashKey::Int(1), Object::Float(2.5))])),
+             Object::Hash(HashMap::from([(HashKey::Int(1), Object::Float(2.5))])), true),
+            (Object::Hash(HashMap::from([(HashKey::Int(1), Object::Float(f64::NAN))])),
+             Object::Hash(HashMap::from([(HashKey::Int(1), Object::Float(f64::NAN))])), false),
+        ];
+        for (a, b, equal) in test_array.iter() {
+            assert_eq!(a == b, *equal, "{:?} - {:?}", a, b);
+        }
+    }
+
+    #[test]
+    fn object_display() {
+        let test_array = [
+            (Object::Array(Vec::new()), "[]"),
+            (Object::Array(vec!(Box::new(Object::Int(1)), Box::new(Object::Int(2)))), "[1, 2]"),
+            (Object::Hash(HashMap::new()), "{}"),
+            (Object::Hash(HashMap::from([(HashKey::Str(String::from("a")), Object::Int(1))])), "{a: 1}"),
+            (Object::Hash(HashMap::from([
+                (HashKey::Str(String::from("b")), Object::Int(2)),
+                (HashKey::Str(String::from("a")), Object::Int(1)),
+            ])), "{a: 1, b: 2}"),
+            (Object::Function {
+                parameters: vec!(
+                    Box::new(Expression::Ident(String::from("x"))),
+                    Box::new(Expression::Ident(String::from("y"))),
+                ),
+                body: Box::new(Statement::Block(Vec::new())),
+                env: Environment::new(),
+            }, "fn(x, y) { ... }"),
+        ];
+        for (obj, expected) in test_array.iter() {
+            assert_eq!(format!("{}", obj), *expected);
+        }
+    }
+
+    #[test]
+    fn environment_get_all() {
+        let outer = Environment::new();
+        outer.set(String::from("b"), Object::Int(2));
+        outer.set(String::from("a"), Object::Int(1));
+        let env = Environment::init(outer.clone());
+        env.set(String::from("c"), Object::Int(3));
+        env.set(String::from("a"), Object::Str(String::from("a")));
+        assert_eq!(outer.get_all(), vec!(
+            (String::from("a"), Object::Int(1)),
+            (String::from("b"), Object::Int(2)),
+        ));
+        assert_eq!(env.get_all(), vec!(
+            (String::from("a"), Object::Str(String::from("a"))),
+            (String::from("b"), Object::Int(2)),
+            (String::from("c"), Object::Int(3)),
+        ));
+        assert_eq!(Environment::new().get_all(), vec!());
     }
 }
@@ -1,3 +1,5 @@
+use std::fmt;
+
 use crate::token::Token;
 use crate::lexer::Lexer;
 use crate::ast::Expression;
@@ -5,41 +7,72 @@ use crate::ast::Statement;
 
 // Precedence table.
 const LOWEST: u8 = 0;
-const EQUALS: u8 = 1;    // ==
-const LESSGREATER: u8 = 2;    // < or >
-const SUM: u8 = 3;    // +
-const PRODUCT: u8 = 4;    // *
-const PREFIX: u8 = 5;    // -X or !X
-const CALL: u8 = 6;    // function()
-const INDEX: u8 = 7;    // arr[0]
+const TERNARY: u8 = 1;    // X ? Y : Z
+const OR: u8 = 2;    // ||
+const AND: u8 = 3;    // &&
+const EQUALS: u8 = 4;    // ==
+const LESSGREATER: u8 = 5;    // < or >
+const BITOR: u8 = 6;    // |
+const BITXOR: u8 = 7;    // ^
+const BITAND: u8 = 8;    // &
+const SHIFT: u8 = 9;    // << or >>
+const SUM: u8 = 10;    // +
+const PRODUCT: u8 = 11;    // * or / or %
+const PREFIX: u8 = 12;    // -X or !X or ~X
+const CALL: u8 = 13;    // function()
+const INDEX: u8 = 14;    // arr[0]
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct ParseError {
+    pub message: String,
+    pub line: usize,
+    pub col: usize,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Parse error at {}:{}: {}", self.line, self.col, self.message)
+    }
+}
 
 pub struct Parser {
-    input: Vec<Token>,
+    input: Vec<(Token, usize, usize)>,    // Vec<(token, line, col)>
     pos: usize,
+    failed: bool,
 }
 
 impl Parser {
     pub fn new(lexer: Lexer) -> Parser {
-        let input = lexer.collect();
+        let input = lexer.filter(|(token, _, _)| !matches!(token, Token::EOF(_))).collect();
         Parser {
             input,
             pos: 0,
+            failed: false,
         }
     }
 
     fn token(&self) -> Option<Token> {
         if self.pos < self.input.len() {
-            Some(self.input[self.pos].clone())
+            Some(self.input[self.pos].0.clone())
         } else {
             None
         }
     }
 
-    fn forward(&mut self) -> () {
+    fn forward(&mut self) {
         self.pos += 1;
     }
 
-    fn assert_and_forward(&mut self, expected: &str) -> String {
+    fn error(&self, message: String) -> ParseError {
+        // Build an error located at the current token, or at the last one on EOF.
+        let (line, col) = match self.input.get(self.pos).or_else(|| self.input.last()) {
+            Some((_, line, col)) => (*line, *col),
+            None => (1, 1),
+        };
+        ParseError { message, line, col }
+    }
+
+    fn assert_and_forward(&mut self, expected: &str) -> Result<String, ParseError> {
         // Assert the current token is of the expected type, then move forward, and
         // finally return this token.
         match self.token() {
@@ -47,93 +80,247 @@ impl Parser {
                 let s = format!("{:?}", tk);
                 let n = match s.find('(') {
                     Some(n) => n,
-                    None => panic!("Invalid Token {:?}", tk),
+                    None => return Err(self.error(format!("Invalid Token {:?}.", tk))),
                 };
                 let name = &s[0..n];    // type
                 let value = &s[n+2..s.len()-2];    // value with () and "" stripped
                 if expected == name {
                     self.forward();
-                    String::from(value)
+                    Ok(String::from(value))
                 } else {
-                    panic!(format!("Expect Token::{}, get {:?}.", expected, tk));
+                    Err(self.error(format!("Expect Token::{}, get {:?}.", expected, tk)))
                 }
             },
-            None => panic!(format!("Expect Token::{}, get EOF.", expected)),
+            None => Err(self.error(format!("Expect Token::{}, get EOF.", expected))),
+        }
+    }
+
+    // Whether every token has been consumed. The iterator stops at the first error,
+    // so a parser that is not finished afterwards has input it never parsed.
+    pub fn finished(&self) -> bool {
+        self.token().is_none()
+    }
+
+    pub fn parse_program(mut self) -> (Vec<Statement>, Vec<ParseError>) {
+        // Parse all statements, skipping to the next semicolon after each error
+        // so that every error in the program can be reported at once.
+        let mut stmts = Vec::new();
+        let mut errors = Vec::new();
+        while !self.finished() {
+            match self.parse_statement() {
+                Ok(stmt) => stmts.push(stmt),
+                Err(err) => {
+                    errors.push(err);
+                    self.synchronize();
+                },
+            };
+        }
+        (stmts, errors)
+    }
+
+    // Parse the whole input as one expression, failing if any token is left over.
+    pub fn parse_single_expression(input: &str) -> Result<Expression, ParseError> {
+        let mut parser = Parser::new(Lexer::new(input));
+        let expr = parser.parse_expression(LOWEST)?;
+        if parser.finished() {
+            Ok(expr)
+        } else {
+            Err(parser.error(format!("Expect EOF, get {:?}.", parser.token().unwrap())))
         }
     }
 
-    fn parse_statement(&mut self) -> Option<Statement> {
+    fn synchronize(&mut self) {
+        // Skip tokens until just after the next semicolon.
+        while let Some(tk) = self.token() {
+            self.forward();
+            if let Token::Semicolon(_) = tk {
+                break;
+            }
+        }
+    }
+
+    fn parse_statement(&mut self) -> Result<Statement, ParseError> {
         match self.token() {
-            Some(Token::Let(_)) => Some(self.parse_let_statement()),
-            Some(Token::Return(_)) => Some(self.parse_return_statement()),
-            Some(_) => Some(self.parse_expr_statement()),
-            None => None,
+            Some(Token::Let(_)) => self.parse_let_statement(),
+            Some(Token::Return(_)) => self.parse_return_statement(),
+            Some(Token::While(_)) => self.parse_while_statement(),
+            Some(Token::Do(_)) => self.parse_do_while_statement(),
+            Some(Token::For(_)) => self.parse_for_statement(),
+            _ => self.parse_expr_statement(),
         }
     }
 
-    fn parse_let_statement(&mut self) -> Statement {
+    fn parse_let_statement(&mut self) -> Result<Statement, ParseError> {
         self.forward();
-        let ident = Expression::Ident(self.assert_and_forward("Ident"));
-        self.assert_and_forward("Assign");
-        let expr = self.parse_expression(LOWEST);
-        self.assert_and_forward("Semicolon");
-        Statement::Let { ident, expr }
+        let ident = Expression::Ident(self.assert_and_forward("Ident")?);
+        self.assert_and_forward("Assign")?;
+        let expr = self.parse_expression(LOWEST)?;
+        self.assert_and_forward("Semicolon")?;
+        Ok(Statement::Let { ident, expr })
     }
 
-    fn parse_return_statement(&mut self) -> Statement {
+    fn parse_return_statement(&mut self) -> Result<Statement, ParseError> {
         self.forward();
-        let expr = self.parse_expression(LOWEST);
-        self.assert_and_forward("Semicolon");
-        Statement::Return(expr)
+        let expr = self.parse_expression(LOWEST)?;
+        self.assert_and_forward("Semicolon")?;
+        Ok(Statement::Return(expr))
     }
 
-    fn parse_expr_statement(&mut self) -> Statement {
-        let expr = self.parse_expression(LOWEST);
+    fn parse_while_statement(&mut self) -> Result<Statement, ParseError> {
+        self.forward();
+        self.assert_and_forward("Lparen")?;
+        let condition = self.parse_expression(LOWEST)?;
+        self.assert_and_forward("Rparen")?;
+        self.assert_and_forward("Lbrace")?;
+        let body = self.parse_block_statement()?;
+        self.assert_and_forward("Rbrace")?;
         if let Some(Token::Semicolon(_)) = self.token() {
             self.forward();
         }
-        Statement::Expr(expr)
+        Ok(Statement::While {
+            condition,
+            body: Box::new(body),
+        })
     }
 
-    fn parse_expression(&mut self, precedence: u8) -> Expression {
-        let mut expr = self.parse_prefix();
+    fn parse_do_while_statement(&mut self) -> Result<Statement, ParseError> {
+        self.forward();
+        self.assert_and_forward("Lbrace")?;
+        let body = self.parse_block_statement()?;
+        self.assert_and_forward("Rbrace")?;
+        self.assert_and_forward("While")?;
+        self.assert_and_forward("Lparen")?;
+        let condition = self.parse_expression(LOWEST)?;
+        self.assert_and_forward("Rparen")?;
+        if let Some(Token::Semicolon(_)) = self.token() {
+            self.forward();
+        }
+        Ok(Statement::DoWhile {
+            body: Box::new(body),
+            condition,
+        })
+    }
+
+    fn parse_for_statement(&mut self) -> Result<Statement, ParseError> {
+        self.forward();
+        self.assert_and_forward("Lparen")?;
+        let init = self.parse_statement()?;
+        let condition = self.parse_expression(LOWEST)?;
+        self.assert_and_forward("Semicolon")?;
+        let update = self.parse_statement()?;
+        self.assert_and_forward("Rparen")?;
+        self.assert_and_forward("Lbrace")?;
+        let body = self.parse_block_statement()?;
+        self.assert_and_forward("Rbrace")?;
+        if let Some(Token::Semicolon(_)) = self.token() {
+            self.forward();
+        }
+        Ok(Statement::For {
+            init: Box::new(init),
+            condition,
+            update: Box::new(update),
+            body: Box::new(body),
+        })
+    }
+
+    fn parse_expr_statement(&mut self) -> Result<Statement, ParseError> {
+        let expr = self.parse_expression(LOWEST)?;
+        self.parse_rest_of_expr_statement(expr)
+    }
+
+    fn parse_rest_of_expr_statement(&mut self, expr: Expression) -> Result<Statement, ParseError> {
+        if let Some(Token::Assign(_)) | Some(Token::PlusAssign(_)) | Some(Token::MinusAssign(_)) |
+               Some(Token::AsteriskAssign(_)) | Some(Token::SlashAssign(_)) = self.token() {
+            return self.parse_assign_statement(expr);
+        }
+        if let Some(Token::Semicolon(_)) = self.token() {
+            self.forward();
+        }
+        Ok(Statement::Expr(expr))
+    }
+
+    fn parse_assign_statement(&mut self, ident: Expression) -> Result<Statement, ParseError> {
+        match &ident {
+            Expression::Ident(_) => (),
+            Expression::Infix { operator, .. } if operator == "[" => (),
+            ident => return Err(self.error(format!("Invalid assignment target {:?}.", ident))),
+        }
+        // Compound assignments like `x += 1` are sugar for `x = x + 1`.
+        let operator = match self.token() {
+            Some(Token::PlusAssign(_)) => Some("+"),
+            Some(Token::MinusAssign(_)) => Some("-"),
+            Some(Token::AsteriskAssign(_)) => Some("*"),
+            Some(Token::SlashAssign(_)) => Some("/"),
+            _ => None,
+        };
+        self.forward();
+        let mut expr = self.parse_expression(LOWEST)?;
+        if let Some(operator) = operator {
+            expr = Expression::Infix {
+                operator: String::from(operator),
+                left: Box::new(ident.clone()),
+                right: Box::new(expr),
+            };
+        }
+        if let Some(Token::Semicolon(_)) = self.token() {
+            self.forward();
+        }
+        Ok(Statement::Assign { ident, expr })
+    }
+
+    fn parse_expression(&mut self, precedence: u8) -> Result<Expression, ParseError> {
+        let mut expr = self.parse_prefix()?;
         while precedence < self.get_precedence(self.token()) {
-            expr = self.parse_infix(expr.clone());
+            expr = self.parse_infix(expr)?;
         }
-        expr
+        Ok(expr)
     }
 
     fn get_precedence(&self, token: Option<Token>) -> u8 {
         match token {
+            Some(Token::Question(_)) => TERNARY,
+            Some(Token::Or(_)) => OR,
+            Some(Token::And(_)) => AND,
             Some(Token::Eq(_)) => EQUALS,
             Some(Token::NotEq(_)) => EQUALS,
             Some(Token::LT(_)) => LESSGREATER,
             Some(Token::GT(_)) => LESSGREATER,
+            Some(Token::Pipe(_)) => BITOR,
+            Some(Token::Caret(_)) => BITXOR,
+            Some(Token::Ampersand(_)) => BITAND,
+            Some(Token::ShiftLeft(_)) => SHIFT,
+            Some(Token::ShiftRight(_)) => SHIFT,
             Some(Token::Plus(_)) => SUM,
             Some(Token::Minus(_)) => SUM,
             Some(Token::Slash(_)) => PRODUCT,
             Some(Token::Asterisk(_)) => PRODUCT,
+            Some(Token::Percent(_)) => PRODUCT,
             Some(Token::Lparen(_)) => CALL,
             Some(Token::Lbracket(_)) => INDEX,
             _ => LOWEST,
         }
     }
 
-    fn parse_prefix(&mut self) -> Expression {
-        let ch = self.token().unwrap();
+    fn parse_prefix(&mut self) -> Result<Expression, ParseError> {
+        let ch = match self.token() {
+            Some(tk) => tk,
+            None => return Err(self.error(String::from("Expect an expression, get EOF."))),
+        };
         self.forward();
-        match ch {
+        let expr = match ch {
             Token::Ident(ident) => Expression::Ident(ident),
             Token::Int(int) => Expression::Int(int),
+            Token::Float(float) => Expression::Float(float),
             Token::Str(s) => Expression::Str(s),
             Token::True(v) | Token::False(v) => Expression::Bool(v),
-            Token::Minus(op) | Token::Bang(op) => Expression::Prefix {
+            Token::Null(_) => Expression::Null,
+            Token::Minus(op) | Token::Plus(op) | Token::Bang(op) | Token::Tilde(op) => Expression::Prefix {
                 operator: op,
-                expr: Box::new(self.parse_expression(PREFIX)),
+                expr: Box::new(self.parse_expression(PREFIX)?),
             },
             Token::Lparen(_) => {
-                let expr = self.parse_expression(LOWEST);
-                self.assert_and_forward("Rparen");
+                let expr = self.parse_expression(LOWEST)?;
+                self.assert_and_forward("Rparen")?;
                 expr
             },
             Token::Lbracket(_) => {
@@ -141,30 +328,41 @@ impl Parser {
                 match self.token() {
                     Some(Token::Rbracket(_)) => (),
                     _ => loop {
-                        list.push(Box::new(self.parse_expression(LOWEST)));
+                        list.push(Box::new(self.parse_expression(LOWEST)?));
                         match self.token() {
                             Some(Token::Comma(_)) => self.forward(),
                             _ => break,
                         };
+                        // A trailing comma may come before the closing delimiter.
+                        if let Some(Token::Rbracket(_)) = self.token() {
+                            break;
+                        }
                     },
                 };
-                self.assert_and_forward("Rbracket");
+                self.assert_and_forward("Rbracket")?;
                 Expression::Array(list)
-            }
+            },
+            Token::Lbrace(_) => self.parse_block_or_hash()?,
             Token::If(_) => {
-                self.assert_and_forward("Lparen");
-                let condition = self.parse_expression(LOWEST);
-                self.assert_and_forward("Rparen");
-                self.assert_and_forward("Lbrace");
-                let consequence = self.parse_block_statement();
-                self.assert_and_forward("Rbrace");
+                self.assert_and_forward("Lparen")?;
+                let condition = self.parse_expression(LOWEST)?;
+                self.assert_and_forward("Rparen")?;
+                self.assert_and_forward("Lbrace")?;
+                let consequence = self.parse_block_statement()?;
+                self.assert_and_forward("Rbrace")?;
                 let alternative = match self.token() {
                     Some(Token::Else(_)) => {
                         self.forward();
-                        self.assert_and_forward("Lbrace");
-                        let alternative = self.parse_block_statement();
-                        self.assert_and_forward("Rbrace");
-                        alternative
+                        if let Some(Token::If(_)) = self.token() {
+                            // Chain `else if` as a block holding another if expression.
+                            let expr = self.parse_prefix()?;
+                            Statement::Block(vec!(Box::new(Statement::Expr(expr))))
+                        } else {
+                            self.assert_and_forward("Lbrace")?;
+                            let alternative = self.parse_block_statement()?;
+                            self.assert_and_forward("Rbrace")?;
+                            alternative
+                        }
                     },
                     _ => Statement::Block(Vec::new()),
                 };
@@ -175,106 +373,214 @@ impl Parser {
                 }
             },
             Token::Function(_) => {
-                self.assert_and_forward("Lparen");
+                self.assert_and_forward("Lparen")?;
                 let mut parameters = Vec::new();
                 match self.token() {
                     Some(Token::Rparen(_)) => (),
                     _ => loop {
-                        match self.token() {
-                            Some(Token::Ident(ident)) => parameters.push(Box::new(Expression::Ident(ident))),
-                            tk => panic!(format!("Expect Token::Ident, get {:?}.", tk)),
-                        };
-                        self.forward();
+                        let ident = self.assert_and_forward("Ident")?;
+                        parameters.push(Box::new(Expression::Ident(ident)));
                         match self.token() {
                             Some(Token::Comma(_)) => self.forward(),
                             _ => break,
                         };
+                        if let Some(Token::Rparen(_)) = self.token() {
+                            break;
+                        }
                     },
                 };
-                self.assert_and_forward("Rparen");
-                self.assert_and_forward("Lbrace");
-                let body = self.parse_block_statement();
-                self.assert_and_forward("Rbrace");
+                self.assert_and_forward("Rparen")?;
+                self.assert_and_forward("Lbrace")?;
+                let body = self.parse_block_statement()?;
+                self.assert_and_forward("Rbrace")?;
                 Expression::Function {
                     parameters,
                     body: Box::new(body),
                 }
             },
-            tk => panic!(format!("Invalid token: {:?}", tk)),
+            Token::Illegal(msg) => {
+                self.pos -= 1;
+                return Err(self.error(msg));
+            },
+            tk => {
+                self.pos -= 1;
+                return Err(self.error(format!("Invalid token {:?}.", tk)));
+            },
+        };
+        Ok(expr)
+    }
+
+    fn parse_block_or_hash(&mut self) -> Result<Expression, ParseError> {
+        // Tell a block from a hash after "{", where a hash starts with "key:" and "{}" is an empty hash.
+        // The first expression is parsed only once, as either the first key or the first statement.
+        let first = match self.token() {
+            Some(Token::Rbrace(_)) => {
+                self.forward();
+                return Ok(Expression::Hash(vec!()));
+            },
+            Some(Token::Let(_)) | Some(Token::Return(_)) | Some(Token::While(_)) |
+            Some(Token::Do(_)) | Some(Token::For(_)) | None => None,
+            _ => Some(self.parse_expression(LOWEST)?),
+        };
+        let stmts = match first {
+            Some(key) if matches!(self.token(), Some(Token::Colon(_))) => return self.parse_hash(key),
+            Some(expr) => vec!(Box::new(self.parse_rest_of_expr_statement(expr)?)),
+            None => vec!(),
+        };
+        let block = self.parse_rest_of_block(stmts)?;
+        self.assert_and_forward("Rbrace")?;
+        Ok(Expression::Block(Box::new(block)))
+    }
+
+    fn parse_hash(&mut self, first_key: Expression) -> Result<Expression, ParseError> {
+        let mut pairs = Vec::new();
+        let mut key = first_key;
+        loop {
+            self.assert_and_forward("Colon")?;
+            let value = self.parse_expression(LOWEST)?;
+            pairs.push((Box::new(key), Box::new(value)));
+            match self.token() {
+                Some(Token::Comma(_)) => self.forward(),
+                _ => break,
+            };
+            if let Some(Token::Rbrace(_)) = self.token() {
+                break;
+            }
+            key = self.parse_expression(LOWEST)?;
         }
+        self.assert_and_forward("Rbrace")?;
+        Ok(Expression::Hash(pairs))
     }
 
-    fn parse_block_statement(&mut self) -> Statement {
-        let mut stmts = Vec::new();
+    fn parse_block_statement(&mut self) -> Result<Statement, ParseError> {
+        self.parse_rest_of_block(Vec::new())
+    }
+
+    fn parse_rest_of_block(&mut self, mut stmts: Vec<Box<Statement>>) -> Result<Statement, ParseError> {
         loop {
             match self.token() {
                 Some(Token::Rbrace(_)) => break,
-                _ => (),
+                None => return Err(self.error(String::from("Expect Token::Rbrace, get EOF."))),
+                _ => stmts.push(Box::new(self.parse_statement()?)),
             };
-            stmts.push(match self.parse_statement() {
-                Some(stmt) => Box::new(stmt),
-                None => panic!("Expect a block statement."),
-            });
         };
-        Statement::Block(stmts)
+        Ok(Statement::Block(stmts))
     }
 
-    fn parse_infix(&mut self, left: Expression) -> Expression {
-        match self.token().unwrap() {
+    fn parse_slice(&mut self, target: Expression, start: Option<Expression>) -> Result<Expression, ParseError> {
+        // Parse the rest of `target[start:end]` from the colon, either bound may be left out.
+        self.forward();
+        let end = match self.token() {
+            Some(Token::Rbracket(_)) => None,
+            _ => Some(Box::new(self.parse_expression(LOWEST)?)),
+        };
+        self.assert_and_forward("Rbracket")?;
+        Ok(Expression::Slice {
+            target: Box::new(target),
+            start: start.map(Box::new),
+            end,
+        })
+    }
+
+    fn parse_infix(&mut self, left: Expression) -> Result<Expression, ParseError> {
+        let expr = match self.token().unwrap() {
             Token::Lparen(_) => {
                 self.forward();
                 let mut arguments = Vec::new();
                 match self.token() {
                     Some(Token::Rparen(_)) => (),
                     _ => loop {
-                        arguments.push(Box::new(self.parse_expression(LOWEST)));
+                        arguments.push(Box::new(self.parse_expression(LOWEST)?));
                         match self.token() {
                             Some(Token::Comma(_)) => self.forward(),
                             _ => break,
                         };
+                        if let Some(Token::Rparen(_)) = self.token() {
+                            break;
+                        }
                     },
                 };
-                self.assert_and_forward("Rparen");
+                self.assert_and_forward("Rparen")?;
                 Expression::Call {
                     function: Box::new(left),
                     arguments,
                 }
             },
+            Token::Lbracket(op) => {
+                // The index is a full expression, so it may contain other indexes like `a[b[0]]`.
+                self.forward();
+                if let Some(Token::Colon(_)) = self.token() {
+                    return self.parse_slice(left, None);
+                }
+                let index = self.parse_expression(LOWEST)?;
+                if let Some(Token::Colon(_)) = self.token() {
+                    return self.parse_slice(left, Some(index));
+                }
+                self.assert_and_forward("Rbracket")?;
+                Expression::Infix {
+                    operator: op,
+                    left: Box::new(left),
+                    right: Box::new(index),
+                }
+            },
+            Token::Question(_) => {
+                // Desugar `X ? Y : Z` into `if (X) { Y } else { Z }`, grouping to the right.
+                self.forward();
+                let consequence = self.parse_expression(LOWEST)?;
+                self.assert_and_forward("Colon")?;
+                let alternative = self.parse_expression(LOWEST)?;
+                Expression::If {
+                    condition: Box::new(left),
+                    consequence: Box::new(Statement::Block(vec!(Box::new(Statement::Expr(consequence))))),
+                    alternative: Box::new(Statement::Block(vec!(Box::new(Statement::Expr(alternative))))),
+                }
+            },
             tk => {
                 let precedence = self.get_precedence(Some(tk.clone()));
                 let operator = match tk {
+                    Token::Or(op) |
+                    Token::And(op) |
                     Token::Eq(op) |
                     Token::NotEq(op) |
                     Token::LT(op) |
                     Token::GT(op) |
+                    Token::Pipe(op) |
+                    Token::Caret(op) |
+                    Token::Ampersand(op) |
+                    Token::ShiftLeft(op) |
+                    Token::ShiftRight(op) |
                     Token::Plus(op) |
                     Token::Minus(op) |
                     Token::Slash(op) |
                     Token::Asterisk(op) |
-                    Token::Lbracket(op) => op,
-                    tk => panic!(format!("Invalid token: {:?}", tk)),
+                    Token::Percent(op) => op,
+                    tk => return Err(self.error(format!("Invalid token {:?}.", tk))),
                 };
                 self.forward();
-                let right = self.parse_expression(precedence);
-                if operator.as_str() == "[" {
-                    self.assert_and_forward("Rbracket");
-                }
+                let right = self.parse_expression(precedence)?;
                 Expression::Infix {
                     operator,
                     left: Box::new(left),
                     right: Box::new(right),
                 }
             },
-        }
+        };
+        Ok(expr)
     }
 }
 
 impl Iterator for Parser {
 
-    type Item = Statement;
+    type Item = Result<Statement, ParseError>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.parse_statement()
+        // Stop after an error, since the position is no longer at a statement boundary.
+        if self.failed || self.finished() {
+            return None;
+        }
+        let result = self.parse_statement();
+        self.failed = result.is_err();
+        Some(result)
     }
 }
 
@@ -284,6 +590,7 @@ mod tests {
 
     use super::Lexer;
     use super::Parser;
+    use super::ParseError;
     use super::Expression;
     use super::Statement;
 
@@ -291,8 +598,10 @@ mod tests {
     fn parser() {
         let input = "
             let x = 10;
+            x = x + 1;
             return 1;
             2;
+            2.5;
             -3;
             !4;
 
@@ -300,11 +609,13 @@ mod tests {
             5 - 5;
             5 * 5;
             5 / 5;
+            5 % 5;
             5 > 5;
             5 < 5;
             5 == 5;
             5 != 5;
 
+            a || b && 5 == 5;
             5 + 5 * 5;
             (5 + 5) * 5;
 
@@ -318,6 +629,8 @@ mod tests {
                 y
             }
 
+            if (x) {x} else if (y) {y}
+
             fn() {}
             fn(x, y) {
                 x
@@ -331,14 +644,33 @@ mod tests {
             [1];
             [1, 2];
             arr[1];
+            arr[1:2];
+            arr[:2];
+            arr[1:];
+
+            {};
+            {\"a\": 1, true: 2 + 3};
+
+            while (x) { x; }
+            do { x; } while (x);
+            for (let i = 0; i < 3; i = i + 1) { i; }
         ";
         let output = [
             Statement::Let {
                 ident: Expression::Ident(String::from("x")),
                 expr: Expression::Int(String::from("10")),
             },
+            Statement::Assign {
+                ident: Expression::Ident(String::from("x")),
+                expr: Expression::Infix {
+                    operator: String::from("+"),
+                    left: Box::new(Expression::Ident(String::from("x"))),
+                    right: Box::new(Expression::Int(String::from("1"))),
+                },
+            },
             Statement::Return(Expression::Int(String::from("1"))),
             Statement::Expr(Expression::Int(String::from("2"))),
+            Statement::Expr(Expression::Float(String::from("2.5"))),
             Statement::Expr(Expression::Prefix {
                 operator: String::from("-"),
                 expr: Box::new(Expression::Int(String::from("3"))),
@@ -368,6 +700,11 @@ mod tests {
                 left: Box::new(Expression::Int(String::from("5"))),
                 right: Box::new(Expression::Int(String::from("5"))),
             }),
+            Statement::Expr(Expression::Infix {
+                operator: String::from("%"),
+                left: Box::new(Expression::Int(String::from("5"))),
+                right: Box::new(Expression::Int(String::from("5"))),
+            }),
             Statement::Expr(Expression::Infix {
                 operator: String::from(">"),
                 left: Box::new(Expression::Int(String::from("5"))),
@@ -389,6 +726,19 @@ mod tests {
                 right: Box::new(Expression::Int(String::from("5"))),
             }),
 
+            Statement::Expr(Expression::Infix {
+                operator: String::from("||"),
+                left: Box::new(Expression::Ident(String::from("a"))),
+                right: Box::new(Expression::Infix {
+                    operator: String::from("&&"),
+                    left: Box::new(Expression::Ident(String::from("b"))),
+                    right: Box::new(Expression::Infix {
+                        operator: String::from("=="),
+                        left: Box::new(Expression::Int(String::from("5"))),
+                        right: Box::new(Expression::Int(String::from("5"))),
+                    }),
+                }),
+            }),
             Statement::Expr(Expression::Infix {
                 operator: String::from("+"),
                 left: Box::new(Expression::Int(String::from("5"))),
@@ -435,6 +785,22 @@ mod tests {
                 ))),
             }),
 
+            Statement::Expr(Expression::If {
+                condition: Box::new(Expression::Ident(String::from("x"))),
+                consequence: Box::new(Statement::Block(vec!(
+                    Box::new(Statement::Expr(Expression::Ident(String::from("x")))),
+                ))),
+                alternative: Box::new(Statement::Block(vec!(
+                    Box::new(Statement::Expr(Expression::If {
+                        condition: Box::new(Expression::Ident(String::from("y"))),
+                        consequence: Box::new(Statement::Block(vec!(
+                            Box::new(Statement::Expr(Expression::Ident(String::from("y")))),
+                        ))),
+                        alternative: Box::new(Statement::Block(Vec::new())),
+                    })),
+                ))),
+            }),
+
             Statement::Expr(Expression::Function {
                 parameters: Vec::new(),
                 body: Box::new(Statement::Block(Vec::new())),
@@ -475,12 +841,399 @@ mod tests {
                 left: Box::new(Expression::Ident(String::from("arr"))),
                 right: Box::new(Expression::Int(String::from("1"))),
             }),
+            Statement::Expr(Expression::Slice {
+                target: Box::new(Expression::Ident(String::from("arr"))),
+                start: Some(Box::new(Expression::Int(String::from("1")))),
+                end: Some(Box::new(Expression::Int(String::from("2")))),
+            }),
+            Statement::Expr(Expression::Slice {
+                target: Box::new(Expression::Ident(String::from("arr"))),
+                start: None,
+                end: Some(Box::new(Expression::Int(String::from("2")))),
+            }),
+            Statement::Expr(Expression::Slice {
+                target: Box::new(Expression::Ident(String::from("arr"))),
+                start: Some(Box::new(Expression::Int(String::from("1")))),
+                end: None,
+            }),
+
+            Statement::Expr(Expression::Hash(Vec::new())),
+            Statement::Expr(Expression::Hash(vec!(
+                (
+                    Box::new(Expression::Str(String::from("a"))),
+                    Box::new(Expression::Int(String::from("1"))),
+                ),
+                (
+                    Box::new(Expression::Bool(String::from("true"))),
+                    Box::new(Expression::Infix {
+                        operator: String::from("+"),
+                        left: Box::new(Expression::Int(String::from("2"))),
+                        right: Box::new(Expression::Int(String::from("3"))),
+                    }),
+                ),
+            ))),
+
+            Statement::While {
+                condition: Expression::Ident(String::from("x")),
+                body: Box::new(Statement::Block(vec!(
+                    Box::new(Statement::Expr(Expression::Ident(String::from("x")))),
+                ))),
+            },
+
+            Statement::DoWhile {
+                body: Box::new(Statement::Block(vec!(
+                    Box::new(Statement::Expr(Expression::Ident(String::from("x")))),
+                ))),
+                condition: Expression::Ident(String::from("x")),
+            },
+
+            Statement::For {
+                init: Box::new(Statement::Let {
+                    ident: Expression::Ident(String::from("i")),
+                    expr: Expression::Int(String::from("0")),
+                }),
+                condition: Expression::Infix {
+                    operator: String::from("<"),
+                    left: Box::new(Expression::Ident(String::from("i"))),
+                    right: Box::new(Expression::Int(String::from("3"))),
+                },
+                update: Box::new(Statement::Assign {
+                    ident: Expression::Ident(String::from("i")),
+                    expr: Expression::Infix {
+                        operator: String::from("+"),
+                        left: Box::new(Expression::Ident(String::from("i"))),
+                        right: Box::new(Expression::Int(String::from("1"))),
+                    },
+                }),
+                body: Box::new(Statement::Block(vec!(
+                    Box::new(Statement::Expr(Expression::Ident(String::from("i")))),
+                ))),
+            },
         ];
         let lexer = Lexer::new(input);
         let parser = Parser::new(lexer);
         for (result, expected) in parser.zip(output.iter()) {
             println!("Parser: {:?} - {:?}", &result, expected);
-            assert_eq!(&result, expected);
+            assert_eq!(&result.unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn parser_trailing_comma() {
+        let test_array = [
+            ("[1, 2,];", "[1, 2];"),
+            ("[1,];", "[1];"),
+            ("{\"a\": 1, \"b\": 2,};", "{\"a\": 1, \"b\": 2};"),
+            ("fn(x, y,) { x };", "fn(x, y) { x };"),
+            ("add(1, 2,);", "add(1, 2);"),
+            ("add(\n    1,\n    2,\n);", "add(1, 2);"),
+        ];
+        for (input, expected) in test_array.iter() {
+            let (program, errors) = Parser::new(Lexer::new(input)).parse_program();
+            let (expected, _) = Parser::new(Lexer::new(expected)).parse_program();
+            assert_eq!(errors, vec!(), "{:?}", input);
+            assert_eq!(program, expected, "{:?}", input);
+        }
+        let (_, errors) = Parser::new(Lexer::new("[1, , 2];")).parse_program();
+        assert_eq!(errors.len(), 1);
+    }
+
+    #[test]
+    fn parser_compound_assign() {
+        let test_array = [
+            ("x += 1;", "x = x + 1;"),
+            ("x -= 1 * 2;", "x = x - (1 * 2);"),
+            ("x *= y - 1", "x = x * (y - 1)"),
+            ("x /= 2;", "x = x / 2;"),
+            ("a[0] += 1;", "a[0] = a[0] + 1;"),
+        ];
+        for (input, expected) in test_array.iter() {
+            let (program, errors) = Parser::new(Lexer::new(input)).parse_program();
+            let (expected, _) = Parser::new(Lexer::new(expected)).parse_program();
+            assert_eq!(errors, vec!(), "{:?}", input);
+            assert_eq!(program, expected, "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn parser_null() {
+        let (program, errors) = Parser::new(Lexer::new("null; let a = [null];")).parse_program();
+        assert_eq!(errors, vec!());
+        assert_eq!(program, vec!(
+            Statement::Expr(Expression::Null),
+            Statement::Let {
+                ident: Expression::Ident(String::from("a")),
+                expr: Expression::Array(vec!(Box::new(Expression::Null))),
+            },
+        ));
+    }
+
+    #[test]
+    fn parser_nested_index() {
+        let index = |left: Expression, right: Expression| Expression::Infix {
+            operator: String::from("["),
+            left: Box::new(left),
+            right: Box::new(right),
+        };
+        let ident = |name: &str| Expression::Ident(String::from(name));
+        let int = |v: &str| Expression::Int(String::from(v));
+        let test_array = [
+            ("a[0][1];", index(index(ident("a"), int("0")), int("1"))),
+            ("a[i][j][k];", index(index(index(ident("a"), ident("i")), ident("j")), ident("k"))),
+            ("a[b[0]][1];", index(index(ident("a"), index(ident("b"), int("0"))), int("1"))),
+            ("a[i + 1][j * 2];", index(index(ident("a"), Expression::Infix {
+                operator: String::from("+"),
+                left: Box::new(ident("i")),
+                right: Box::new(int("1")),
+            }), Expression::Infix {
+                operator: String::from("*"),
+                left: Box::new(ident("j")),
+                right: Box::new(int("2")),
+            })),
+            ("f()[0][1];", index(index(Expression::Call {
+                function: Box::new(ident("f")),
+                arguments: vec!(),
+            }, int("0")), int("1"))),
+        ];
+        for (input, expected) in test_array.iter() {
+            let (program, errors) = Parser::new(Lexer::new(input)).parse_program();
+            assert_eq!(errors, vec!());
+            assert_eq!(program, vec!(Statement::Expr(expected.clone())), "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn parser_block() {
+        let int = |v: &str| Box::new(Expression::Int(String::from(v)));
+        let test_array = [
+            ("{ let x = 1; x + 1 }", Expression::Block(Box::new(Statement::Block(vec!(
+                Box::new(Statement::Let {
+                    ident: Expression::Ident(String::from("x")),
+                    expr: Expression::Int(String::from("1")),
+                }),
+                Box::new(Statement::Expr(Expression::Infix {
+                    operator: String::from("+"),
+                    left: Box::new(Expression::Ident(String::from("x"))),
+                    right: int("1"),
+                })),
+            ))))),
+            ("{ 1; 2 }", Expression::Block(Box::new(Statement::Block(vec!(
+                Box::new(Statement::Expr(Expression::Int(String::from("1")))),
+                Box::new(Statement::Expr(Expression::Int(String::from("2")))),
+            ))))),
+            ("{a: 1}", Expression::Hash(vec!((Box::new(Expression::Ident(String::from("a"))), int("1"))))),
+            ("{1 + 1: 2, 3: 4}", Expression::Hash(vec!(
+                (Box::new(Expression::Infix {
+                    operator: String::from("+"),
+                    left: int("1"),
+                    right: int("1"),
+                }), int("2")),
+                (int("3"), int("4")),
+            ))),
+            ("{}", Expression::Hash(vec!())),
+            ("{ x = 2; x }", Expression::Block(Box::new(Statement::Block(vec!(
+                Box::new(Statement::Assign {
+                    ident: Expression::Ident(String::from("x")),
+                    expr: Expression::Int(String::from("2")),
+                }),
+                Box::new(Statement::Expr(Expression::Ident(String::from("x")))),
+            ))))),
+            ("{{}: {1: 2}}", Expression::Hash(vec!(
+                (Box::new(Expression::Hash(vec!())), Box::new(Expression::Hash(vec!((int("1"), int("2")))))),
+            ))),
+        ];
+        for (input, expected) in test_array.iter() {
+            let (program, errors) = Parser::new(Lexer::new(input)).parse_program();
+            assert_eq!(errors, vec!());
+            assert_eq!(program, vec!(Statement::Expr(expected.clone())), "{:?}", input);
         }
+        // Every level is parsed once, so deep nesting does not blow up.
+        let mut expected = Expression::Int(String::from("1"));
+        for _ in 0..100 {
+            expected = Expression::Block(Box::new(Statement::Block(vec!(Box::new(Statement::Expr(expected))))));
+        }
+        let input = format!("{}1{}", "{".repeat(100), "}".repeat(100));
+        let (program, errors) = Parser::new(Lexer::new(&input)).parse_program();
+        assert_eq!(errors, vec!());
+        assert_eq!(program, vec!(Statement::Expr(expected)));
+    }
+
+    #[test]
+    fn parser_ternary() {
+        let test_array = [
+            ("x > 0 ? \"pos\" : \"neg\";", "if (x > 0) { \"pos\" } else { \"neg\" };"),
+            ("a || b ? 1 + 2 : 3;", "if (a || b) { 1 + 2 } else { 3 };"),
+            ("a ? 1 : b ? 2 : 3;", "if (a) { 1 } else { if (b) { 2 } else { 3 } };"),
+            ("let x = a ? b ? 1 : 2 : 3;", "let x = if (a) { if (b) { 1 } else { 2 } } else { 3 };"),
+            ("f(a ? 1 : 2, {\"k\": a ? 3 : 4});", "f(if (a) { 1 } else { 2 }, {\"k\": if (a) { 3 } else { 4 }});"),
+        ];
+        for (input, expected) in test_array.iter() {
+            let (program, errors) = Parser::new(Lexer::new(input)).parse_program();
+            let (expected, _) = Parser::new(Lexer::new(expected)).parse_program();
+            assert_eq!(errors, vec!(), "{:?}", input);
+            assert_eq!(program, expected, "{:?}", input);
+        }
+        let (program, _) = Parser::new(Lexer::new("a ? 1 : 2;")).parse_program();
+        assert_eq!(program, vec!(Statement::Expr(Expression::If {
+            condition: Box::new(Expression::Ident(String::from("a"))),
+            consequence: Box::new(Statement::Block(vec!(
+                Box::new(Statement::Expr(Expression::Int(String::from("1")))),
+            ))),
+            alternative: Box::new(Statement::Block(vec!(
+                Box::new(Statement::Expr(Expression::Int(String::from("2")))),
+            ))),
+        })));
+    }
+
+    #[test]
+    fn parser_bitwise() {
+        let infix = |operator: &str, left: Expression, right: Expression| Expression::Infix {
+            operator: String::from(operator),
+            left: Box::new(left),
+            right: Box::new(right),
+        };
+        let ident = |name: &str| Expression::Ident(String::from(name));
+        let int = |v: &str| Expression::Int(String::from(v));
+        let test_array = [
+            ("a | b ^ c & d << 1 == e;", infix("==",
+                infix("|", ident("a"), infix("^", ident("b"), infix("&", ident("c"), infix("<<", ident("d"), int("1"))))),
+                ident("e"))),
+            ("a >> 1 + 2 & 3;", infix("&", infix(">>", ident("a"), infix("+", int("1"), int("2"))), int("3"))),
+            ("a & b | c < d;", infix("<", infix("|", infix("&", ident("a"), ident("b")), ident("c")), ident("d"))),
+            ("~a & 1;", infix("&", Expression::Prefix {
+                operator: String::from("~"),
+                expr: Box::new(ident("a")),
+            }, int("1"))),
+            ("+5 - +a;", infix("-", Expression::Prefix {
+                operator: String::from("+"),
+                expr: Box::new(int("5")),
+            }, Expression::Prefix {
+                operator: String::from("+"),
+                expr: Box::new(ident("a")),
+            })),
+        ];
+        for (input, expected) in test_array.iter() {
+            let lexer = Lexer::new(input);
+            let parser = Parser::new(lexer);
+            let (program, errors) = parser.parse_program();
+            assert_eq!(errors, vec!());
+            assert_eq!(program, vec!(Statement::Expr(expected.clone())), "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn parser_error() {
+        let test_array = [
+            ("let = 1;", "Expect Token::Ident, get Assign(\"=\").", 1, 5),
+            ("let x = 1;\n(1 + 2;", "Expect Token::Rparen, get Semicolon(\";\").", 2, 7),
+            ("add(1,\n    2;", "Expect Token::Rparen, get Semicolon(\";\").", 2, 6),
+            ("if (x) {\n  x", "Expect Token::Rbrace, get EOF.", 2, 3),
+            ("1 +", "Expect an expression, get EOF.", 1, 3),
+            ("\n  ;", "Invalid token Semicolon(\";\").", 2, 3),
+            ("/* a", "Unterminated block comment.", 1, 1),
+            ("let s = \"abc;", "Unterminated string.", 1, 9),
+            ("let a = @;", "Invalid character @.", 1, 9),
+            ("a ? 1;", "Expect Token::Colon, get Semicolon(\";\").", 1, 6),
+            ("1 = 2;", "Invalid assignment target Int(\"1\").", 1, 3),
+            ("1 += 2;", "Invalid assignment target Int(\"1\").", 1, 3),
+            ("f() = 2;", "Invalid assignment target Call { function: Ident(\"f\"), arguments: [] }.", 1, 5),
+        ];
+        for (input, message, line, col) in test_array.iter() {
+            let lexer = Lexer::new(input);
+            let parser = Parser::new(lexer);
+            let result: Result<Vec<_>, _> = parser.collect();
+            println!("Parser: {:?} - {:?}", input, result);
+            assert_eq!(result, Err(ParseError {
+                message: String::from(*message),
+                line: *line,
+                col: *col,
+            }));
+        }
+    }
+
+    #[test]
+    fn parser_single_expression() {
+        let result = Parser::parse_single_expression("1 + 2 * 3");
+        assert_eq!(result, Ok(Expression::Infix {
+            operator: String::from("+"),
+            left: Box::new(Expression::Int(String::from("1"))),
+            right: Box::new(Expression::Infix {
+                operator: String::from("*"),
+                left: Box::new(Expression::Int(String::from("2"))),
+                right: Box::new(Expression::Int(String::from("3"))),
+            }),
+        }));
+        let test_array = [
+            ("1 + 2;", "Expect EOF, get Semicolon(\";\").", 1, 6),
+            ("1 2", "Expect EOF, get Int(\"2\").", 1, 3),
+            ("1 + 2 )", "Expect EOF, get Rparen(\")\").", 1, 7),
+            ("f(1) x", "Expect EOF, get Ident(\"x\").", 1, 6),
+            ("", "Expect an expression, get EOF.", 1, 1),
+        ];
+        for (input, message, line, col) in test_array.iter() {
+            let result = Parser::parse_single_expression(input);
+            println!("Parser: {:?} - {:?}", input, result);
+            assert_eq!(result, Err(ParseError {
+                message: String::from(*message),
+                line: *line,
+                col: *col,
+            }));
+        }
+    }
+
+    #[test]
+    fn parser_finished() {
+        let test_array = [
+            ("", true),
+            ("1; 2;", true),
+            ("let = 1; 2;", false),
+            ("1 + 2 )", false),
+        ];
+        for (input, finished) in test_array.iter() {
+            let mut parser = Parser::new(Lexer::new(input));
+            let errors = parser.by_ref().filter(|result| result.is_err()).count();
+            println!("Parser: {:?} - {:?}", input, errors);
+            assert_eq!(parser.finished(), *finished);
+            assert_eq!(errors == 0, *finished);
+        }
+        // The whole program is still parsed, with the trailing token reported.
+        let (stmts, errors) = Parser::new(Lexer::new("1 + 2 )")).parse_program();
+        assert_eq!(stmts.len(), 1);
+        assert_eq!(errors, vec!(ParseError {
+            message: String::from("Invalid token Rparen(\")\")."),
+            line: 1,
+            col: 7,
+        }));
+    }
+
+    #[test]
+    fn parser_program() {
+        let input = "
+            let = 1;
+            let y = 2;
+            (1 + ;
+        ";
+        let lexer = Lexer::new(input);
+        let parser = Parser::new(lexer);
+        let (stmts, errors) = parser.parse_program();
+        println!("Parser: {:?} - {:?}", stmts, errors);
+        assert_eq!(stmts, vec!(
+            Statement::Let {
+                ident: Expression::Ident(String::from("y")),
+                expr: Expression::Int(String::from("2")),
+            },
+        ));
+        assert_eq!(errors, vec!(
+            ParseError {
+                message: String::from("Expect Token::Ident, get Assign(\"=\")."),
+                line: 2,
+                col: 17,
+            },
+            ParseError {
+                message: String::from("Invalid token Semicolon(\";\")."),
+                line: 4,
+                col: 18,
+            },
+        ));
     }
 }
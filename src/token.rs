@@ -1,26 +1,43 @@
 #[derive(PartialEq, Debug, Clone)]
 pub enum Token {
     EOF(String),    // ""
+    Illegal(String),    // error message of malformed input
 
     // identifiers + literals
     Ident(String),    // indentifier
     Int(String),    // integer
+    Float(String),    // float
     Str(String),    // string
 
     // operators
     Assign(String),    // "="
+    PlusAssign(String),    // "+="
+    MinusAssign(String),    // "-="
+    AsteriskAssign(String),    // "*="
+    SlashAssign(String),    // "/="
     Plus(String),    // "+"
     Minus(String),    // "-"
     Asterisk(String),    // "*"
     Slash(String),    // "/"
+    Percent(String),    // "%"
     Bang(String),    // "!"
+    Tilde(String),    // "~"
     LT(String),    // "<"
     GT(String),    // ">"
     Eq(String),    // "=="
     NotEq(String),    // "!="
+    And(String),    // "&&"
+    Or(String),    // "||"
+    Ampersand(String),    // "&"
+    Pipe(String),    // "|"
+    Caret(String),    // "^"
+    ShiftLeft(String),    // "<<"
+    ShiftRight(String),    // ">>"
 
     // delimiters
     Comma(String),    // ","
+    Colon(String),    // ":"
+    Question(String),    // "?"
     Semicolon(String),    // ";"
 
     Lparen(String),    // "("
@@ -37,5 +54,9 @@ pub enum Token {
     Else(String),    // "else"
     True(String),    // "true"
     False(String),    // "false"
+    Null(String),    // "null"
     Return(String),    // "return"
+    While(String),    // "while"
+    Do(String),    // "do"
+    For(String),    // "for"
 }
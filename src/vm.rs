@@ -1,4 +1,7 @@
 use std::collections::HashMap;
+use std::fmt;
+use std::mem;
+use std::rc::Rc;
 
 use crate::code::Code;
 use crate::code::SymbolTable;
@@ -6,252 +9,529 @@ use crate::lexer::Lexer;
 use crate::parser::Parser;
 use crate::compiler::Compiler;
 use crate::object::Object;
+use crate::object::array_index;
+use crate::object::float_operands;
+use crate::object::repeat_str;
+use crate::object::is_truthy;
+use crate::builtins::BUILTINS;
+use crate::builtins::call_builtin;
 
 const TRUE: Object = Object::Bool(true);
 const FALSE: Object = Object::Bool(false);
 const NULL: Object = Object::Null;
+const MAX_FRAMES: usize = 1024;
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct RuntimeError {
+    pub message: String,
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Errors look the same as the error objects of the evaluator.
+        write!(f, "Error: {}", self.message)
+    }
+}
 
 #[derive(Clone)]
 struct Frame {
-    instructions: Vec<Code>,
+    instructions: Rc<Vec<Code>>,    // instructions of the caller
+    ip: usize,    // instruction pointer of the caller
     base: usize,
+    outer_base: usize,
+    function: Option<Object>,    // function of the caller
 }
 
 pub struct VM {
     frames: Vec<Frame>,
-    instructions: Vec<Code>,
+    instructions: Rc<Vec<Code>>,
+    ip: usize,
     stack: Vec<Object>,
     base: usize,
+    function: Option<Object>,
     last_popped: Option<Object>,
-    jump: usize,
     globals: HashMap<usize, Object>,
+    constants: Vec<Object>,
+    max_frames: usize,
+    error: Option<RuntimeError>,
 }
 
 impl VM {
-    pub fn new(mut instructions: Vec<Code>, globals: HashMap<usize, Object>) -> VM {
-        instructions.reverse();
+    pub fn new(instructions: Vec<Code>, constants: Vec<Object>,
+               globals: HashMap<usize, Object>) -> VM {
+        VM::with_limits(instructions, constants, globals, MAX_FRAMES)
+    }
+
+    pub fn with_limits(instructions: Vec<Code>, constants: Vec<Object>,
+                       globals: HashMap<usize, Object>, max_frames: usize) -> VM {
         VM {
             frames: vec!(),
-            instructions,
+            instructions: Rc::new(instructions),
+            ip: 0,
             stack: vec!(),
             base: 0,
+            function: None,
             last_popped: None,
-            jump: 0,
             globals,
+            constants,
+            max_frames,
+            error: None,
         }
     }
 
-    pub fn run(mut self) -> (Object, Option<Object>, HashMap<usize, Object>) {
-        loop {
-            match self.instructions.pop() {
-                Some(code) => {
-                    if self.jump == 0 {
-                        self.execute(code);
-                    } else {
-                        self.jump -= 1;
-                    };
-                },
-                None => break,
-            };
+    #[allow(clippy::type_complexity)]
+    pub fn run(mut self) -> Result<(Object, Option<Object>, HashMap<usize, Object>), RuntimeError> {
+        while self.ip < self.instructions.len() {
+            let code = self.instructions[self.ip].clone();
+            self.ip += 1;
+            self.execute(code);
         };
+        if let Some(err) = self.error {
+            return Err(err);
+        }
         match self.stack.pop() {
-            Some(obj) => (obj, self.last_popped, self.globals),
-            None => (NULL, self.last_popped, self.globals),
+            Some(obj) => Ok((obj, self.last_popped, self.globals)),
+            None => Ok((NULL, self.last_popped, self.globals)),
         }
     }
 
     fn execute(&mut self, code: Code) {
+        // Bytecode may come from a file, so the stack and the operands are checked before use.
+        if !self.has_operands(&code) {
+            return self.halt(format!("Stack underflow at {}.", code));
+        }
         match code {
-            Code::Constant(obj) => self.stack.push(obj),
+            Code::Constant(index) => match self.constants.get(index) {
+                Some(obj) => self.stack.push(obj.clone()),
+                None => self.halt(format!("Constant {} does not exist.", index)),
+            },
             op @ Code::Add | op @ Code::Sub |
-            op @ Code::Mul | op @ Code::Div => self.execute_arithmetic(op),
+            op @ Code::Mul | op @ Code::Div |
+            op @ Code::Mod | op @ Code::BitAnd |
+            op @ Code::BitOr | op @ Code::BitXor |
+            op @ Code::ShiftLeft | op @ Code::ShiftRight => self.execute_arithmetic(op),
             op @ Code::Equal | op @ Code::NotEqual |
-            op @ Code::GreaterThan | op @ Code::LessThan => self.execute_comparison(op),
+            op @ Code::GreaterThan | op @ Code::LessThan |
+            op @ Code::And | op @ Code::Or => self.execute_comparison(op),
             Code::True => self.stack.push(TRUE),
             Code::False => self.stack.push(FALSE),
-            op @ Code::Minus | op @ Code::Bang => self.execute_prefix(op),
+            op @ Code::Minus | op @ Code::Bang |
+            op @ Code::BitNot | op @ Code::Plus => self.execute_prefix(op),
             Code::Pop => { self.last_popped = self.stack.pop(); },
-            Code::JumpNotTruthy(offset) => self.execute_jump_not_truthy(offset),
-            Code::Jump(offset) => self.execute_jump(offset),
+            Code::JumpNotTruthy(target) => self.execute_jump_not_truthy(target),
+            Code::Jump(target) => self.ip = target,
             Code::Null => self.stack.push(NULL),
-            Code::SetGlobal(index) => { self.globals.insert(index, self.stack.pop().unwrap()); },
-            Code::GetGlobal(index) => { self.stack.push(self.globals.get(&index).unwrap().clone()); },
+            Code::SetGlobal(index) => {
+                let value = self.pop();
+                self.globals.insert(index, value);
+            },
+            Code::GetGlobal(index) => match self.globals.get(&index) {
+                Some(obj) => self.stack.push(obj.clone()),
+                None => self.halt(format!("Global {} is not initialized.", index)),
+            },
             Code::Array(size) => self.execute_array(size),
+            Code::Hash(size) => self.execute_hash(size),
             Code::Index => self.execute_index(),
             Code::ReturnValue => self.execute_return_value(),
             Code::Return => self.execute_return(),
             Code::Call(num_args) => self.execute_call(num_args),
             Code::SetLocal(index) => { self.stack.swap_remove(self.base+index); },
-            Code::GetLocal(index) => { self.stack.push(self.stack.get(self.base+index).unwrap().clone()); },
+            Code::GetLocal(index) => self.stack.push(self.stack[self.base+index].clone()),
+            Code::CurrentFunction => match self.function.clone() {
+                Some(function) => self.stack.push(function),
+                None => self.halt(String::from("No current function outside of a function.")),
+            },
+            Code::Dup => self.stack.push(self.top().clone()),
+            Code::GetBuiltin(index) => match BUILTINS.get(index) {
+                Some(name) => self.stack.push(Object::Builtin(String::from(*name))),
+                None => self.halt(format!("Builtin {} does not exist.", index)),
+            },
         }
     }
 
-    fn push_frame(&mut self, mut instructions: Vec<Code>, base: usize) {
+    fn has_operands(&self, code: &Code) -> bool {
+        // Whether the stack holds every value the instruction takes.
+        let len = self.stack.len();
+        match *code {
+            // The value on top of the stack replaces the local below it.
+            Code::SetLocal(index) => self.base.saturating_add(index) < len.saturating_sub(1),
+            Code::GetLocal(index) => self.base.saturating_add(index) < len,
+            Code::Array(size) => size <= len,
+            Code::Hash(size) => size.saturating_mul(2) <= len,
+            Code::Call(num_args) => num_args < len,
+            Code::Add | Code::Sub | Code::Mul | Code::Div | Code::Mod |
+            Code::BitAnd | Code::BitOr | Code::BitXor | Code::ShiftLeft | Code::ShiftRight |
+            Code::Equal | Code::NotEqual | Code::GreaterThan | Code::LessThan |
+            Code::And | Code::Or | Code::Index => len >= 2,
+            Code::Minus | Code::Bang | Code::BitNot | Code::Plus | Code::Pop |
+            Code::JumpNotTruthy(_) | Code::SetGlobal(_) | Code::ReturnValue | Code::Dup => len >= 1,
+            Code::Constant(_) | Code::True | Code::False | Code::Null | Code::Jump(_) |
+            Code::GetGlobal(_) | Code::Return | Code::CurrentFunction | Code::GetBuiltin(_) => true,
+        }
+    }
+
+    fn pop(&mut self) -> Object {
+        // Every instruction is checked by has_operands first, so the value is there.
+        self.stack.pop().expect("Stack underflow.")
+    }
+
+    fn top(&self) -> &Object {
+        self.stack.last().expect("Stack underflow.")
+    }
+
+    fn halt(&mut self, message: String) {
+        // Stop the execution, `run` reports the error instead of a result.
+        self.ip = self.instructions.len();
+        self.frames.clear();
+        self.error = Some(RuntimeError { message });
+    }
+
+    fn push_frame(&mut self, function: Object, instructions: Rc<Vec<Code>>, base: usize) {
         self.frames.push(Frame {
-            instructions: self.instructions.clone(),
+            instructions: mem::replace(&mut self.instructions, instructions),
+            ip: self.ip,
             base,
+            outer_base: self.base,
+            function: self.function.replace(function),
         });
-        instructions.reverse();
-        self.instructions = instructions;
+        self.ip = 0;
         self.base = base;
     }
 
     fn pop_frame(&mut self) {
-        let Frame { instructions, base } = self.frames.pop().unwrap();
+        let Frame { instructions, ip, base, outer_base, function } = match self.frames.pop() {
+            Some(frame) => frame,
+            None => return self.halt(String::from("Return outside of a function.")),
+        };
         self.instructions = instructions;
+        self.ip = ip;
+        self.base = outer_base;
+        self.function = function;
         while self.stack.len() > base {
             self.stack.pop();
         }
     }
 
     fn execute_arithmetic(&mut self, op: Code) {
-        let right = self.stack.pop().unwrap();
-        if let Object::Int(right) = right {
-            let left = self.stack.pop().unwrap();
-            if let Object::Int(left) = left {
+        let right = self.pop();
+        if let Some((left, right)) = float_operands(self.top(), &right) {
+            self.stack.pop();
+            let value = match op {
+                Code::Add => left + right,
+                Code::Sub => left - right,
+                Code::Mul => left * right,
+                Code::Div => left / right,
+                Code::Mod => left % right,
+                op => return self.halt(format!("Unexpected arithmatic operator {:?}.", op)),
+            };
+            self.stack.push(Object::Float(value));
+        } else if let Object::Int(right) = right {
+            let left = self.pop();
+            if let (Object::Str(left), Code::Mul) = (&left, &op) {
+                match repeat_str(left, right) {
+                    Some(value) => self.stack.push(Object::Str(value)),
+                    None => self.halt(String::from("String too long.")),
+                };
+            } else if let Object::Int(left) = left {
+                if right == 0 && (op == Code::Div || op == Code::Mod) {
+                    return self.halt(String::from("Division by zero."));
+                }
+                if !(0..64).contains(&right) && (op == Code::ShiftLeft || op == Code::ShiftRight) {
+                    return self.halt(format!("Invalid shift amount {}.", right));
+                }
                 let value = match op {
-                    Code::Add => left + right,
-                    Code::Sub => left - right,
-                    Code::Mul => left * right,
-                    Code::Div => left / right,
-                    op => panic!("Unexpected arithmatic operator {:?}.", op),
+                    Code::Add => left.checked_add(right),
+                    Code::Sub => left.checked_sub(right),
+                    Code::Mul => left.checked_mul(right),
+                    Code::Div => left.checked_div(right),
+                    Code::Mod => left.checked_rem(right),
+                    Code::BitAnd => Some(left & right),
+                    Code::BitOr => Some(left | right),
+                    Code::BitXor => Some(left ^ right),
+                    Code::ShiftLeft => Some(left << right),
+                    Code::ShiftRight => Some(left >> right),
+                    op => return self.halt(format!("Unexpected arithmatic operator {:?}.", op)),
+                };
+                match value {
+                    Some(value) => self.stack.push(Object::Int(value)),
+                    None => self.halt(String::from("Integer overflow.")),
                 };
-                self.stack.push(Object::Int(value));
             } else {
-                panic!("Expect Object::Int, get {}.", left);
+                self.halt(format!("Expect Object::Int, get {}.", left));
             };
         } else if let Object::Str(right) = right {
-            let left = self.stack.pop().unwrap();
-            if let Object::Str(left) = left {
+            let left = self.pop();
+            if let (Object::Int(left), Code::Mul) = (&left, &op) {
+                match repeat_str(&right, *left) {
+                    Some(value) => self.stack.push(Object::Str(value)),
+                    None => self.halt(String::from("String too long.")),
+                };
+            } else if let Object::Str(left) = left {
                 let value = match op {
                     Code::Add => left + &right,
-                    op => panic!("Unexpected arithmatic operator {:?}.", op),
+                    op => return self.halt(format!("Unexpected arithmatic operator {:?}.", op)),
                 };
                 self.stack.push(Object::Str(value));
             } else {
-                panic!("Expect Object::Str, get {}.", left);
+                self.halt(format!("Expect Object::Str, get {}.", left));
+            };
+        } else if let Object::Array(right) = right {
+            match (self.pop(), op) {
+                (Object::Array(left), Code::Add) => {
+                    // Build a new array, the operands may still be referenced elsewhere.
+                    let value = left.iter().chain(right.iter()).cloned().collect();
+                    self.stack.push(Object::Array(value));
+                },
+                (Object::Array(_), op) => self.halt(format!("Unexpected arithmatic operator {:?}.", op)),
+                (left, _) => self.halt(format!("Expect Object::Array, get {}.", left)),
             };
         } else {
-            panic!("Expect Object::Int or Object::Str, get {}.", right);
+            self.halt(format!("Expect Object::Int, Object::Float, Object::Str or Object::Array, get {}.", right));
         };
     }
 
     fn execute_comparison(&mut self, op: Code) {
-        let obj_right = self.stack.pop().unwrap();
-        if let Object::Int(right) = obj_right {
-            let obj_left = self.stack.pop().unwrap();
+        let obj_right = self.pop();
+        if (op == Code::Equal || op == Code::NotEqual) && (obj_right == NULL || *self.top() == NULL) {
+            // Anything can be compared with null, which only equals itself.
+            let obj_left = self.pop();
+            self.stack.push(Object::Bool((obj_left == obj_right) == (op == Code::Equal)));
+        } else if let Some((left, right)) = float_operands(self.top(), &obj_right) {
+            self.stack.pop();
+            match op {
+                Code::Equal => self.stack.push(Object::Bool(left==right)),
+                Code::NotEqual => self.stack.push(Object::Bool(left!=right)),
+                Code::GreaterThan => self.stack.push(Object::Bool(left>right)),
+                Code::LessThan => self.stack.push(Object::Bool(left<right)),
+                op => self.halt(format!("Unknown operator {:?}.", op)),
+            }
+        } else if let Object::Int(right) = obj_right {
+            let obj_left = self.pop();
             if let Object::Int(left) = obj_left {
                 match op {
                     Code::Equal => self.stack.push(Object::Bool(left==right)),
                     Code::NotEqual => self.stack.push(Object::Bool(left!=right)),
                     Code::GreaterThan => self.stack.push(Object::Bool(left>right)),
                     Code::LessThan => self.stack.push(Object::Bool(left<right)),
-                    op => panic!("Unknown operator {:?}.", op),
+                    op => self.halt(format!("Unknown operator {:?}.", op)),
                 }
             } else {
-                panic!("Expect Object::Int, get {}.", obj_left);
+                self.halt(format!("Expect Object::Int, get {}.", obj_left));
             };
         } else if let Object::Bool(right) = obj_right {
-            let obj_left = self.stack.pop().unwrap();
+            let obj_left = self.pop();
             if let Object::Bool(left) = obj_left {
                 match op {
                     Code::Equal => self.stack.push(Object::Bool(left==right)),
                     Code::NotEqual => self.stack.push(Object::Bool(left!=right)),
-                    op => panic!("Unknown operator {:?}.", op),
+                    Code::And => self.stack.push(Object::Bool(left&&right)),
+                    Code::Or => self.stack.push(Object::Bool(left||right)),
+                    op => self.halt(format!("Unknown operator {:?}.", op)),
                 }
             } else {
-                panic!("Expect Object::Bool, get {}.", obj_left);
+                self.halt(format!("Expect Object::Bool, get {}.", obj_left));
+            };
+        } else if let Object::Str(right) = obj_right {
+            let obj_left = self.pop();
+            if let Object::Str(left) = obj_left {
+                match op {
+                    Code::Equal => self.stack.push(Object::Bool(left==right)),
+                    Code::NotEqual => self.stack.push(Object::Bool(left!=right)),
+                    Code::GreaterThan => self.stack.push(Object::Bool(left>right)),
+                    Code::LessThan => self.stack.push(Object::Bool(left<right)),
+                    op => self.halt(format!("Unknown operator {:?}.", op)),
+                }
+            } else {
+                self.halt(format!("Expect Object::Str, get {}.", obj_left));
             };
         } else {
-            panic!("Expect Object::Bool or Object::Int, get {}.", obj_right);
+            self.halt(format!("Expect Object::Bool, Object::Int, Object::Float or Object::Str, get {}.", obj_right));
         };
     }
 
     fn execute_prefix(&mut self, operator: Code) {
         match operator {
             Code::Minus => {
-                match self.stack.pop().unwrap() {
-                    Object::Int(v) => self.stack.push(Object::Int(-v)),
-                    obj => panic!("Expect Object::Int, get {:?}.", obj),
+                match self.pop() {
+                    Object::Int(v) => match v.checked_neg() {
+                        Some(v) => self.stack.push(Object::Int(v)),
+                        None => self.halt(String::from("Integer overflow.")),
+                    },
+                    Object::Float(v) => self.stack.push(Object::Float(-v)),
+                    obj => self.halt(format!("Expect Object::Int or Object::Float, get {}.", obj)),
                 };
             },
             Code::Bang => {
-                match self.stack.pop().unwrap() {
-                    Object::Bool(v) => self.stack.push(Object::Bool(!v)),
-                    NULL => self.stack.push(Object::Bool(true)),
-                    obj => panic!("Expect Object::Bool, get {:?}.", obj),
+                let obj = self.pop();
+                self.stack.push(Object::Bool(!is_truthy(&obj)));
+            },
+            Code::BitNot => {
+                match self.pop() {
+                    Object::Int(v) => self.stack.push(Object::Int(!v)),
+                    obj => self.halt(format!("Expect Object::Int, get {}.", obj)),
+                };
+            },
+            Code::Plus => {
+                match self.pop() {
+                    obj @ Object::Int(_) | obj @ Object::Float(_) => self.stack.push(obj),
+                    obj => self.halt(format!("Expect Object::Int or Object::Float, get {}.", obj)),
                 };
             },
             _ => (),
         }
     }
 
-    fn execute_jump_not_truthy(&mut self, offset: usize) {
-        match self.stack.pop().unwrap() {
-            Object::Bool(false) | NULL => self.execute_jump(offset),
-            _ => (),
+    fn execute_jump_not_truthy(&mut self, target: usize) {
+        if !is_truthy(&self.pop()) {
+            self.ip = target;
         }
     }
 
-    fn execute_jump(&mut self, offset: usize) {
-        self.jump = offset;
-    }
-
     fn execute_array(&mut self, size: usize) {
         let mut array = Vec::new();
         for _ in 0..size {
-            array.push(Box::new(self.stack.pop().unwrap()));
+            array.push(Box::new(self.pop()));
         }
         array.reverse();
         self.stack.push(Object::Array(array));
     }
 
+    fn execute_hash(&mut self, size: usize) {
+        let mut pairs = Vec::new();
+        for _ in 0..size {
+            let value = self.pop();
+            let key = self.pop();
+            pairs.push((key, value));
+        }
+        pairs.reverse();
+        let mut hash = HashMap::new();
+        for (key, value) in pairs.into_iter() {
+            let key = match key.hashable() {
+                Some(key) => key,
+                None => return self.halt(format!("Unusable as hash key: {}.", key)),
+            };
+            // A repeated key overrides the previous value.
+            hash.insert(key, value);
+        }
+        self.stack.push(Object::Hash(hash));
+    }
+
     fn execute_index(&mut self) {
-        let index = match self.stack.pop().unwrap() {
-            Object::Int(v) => v,
-            obj => panic!("Expect Object::Int, get {:?}.", obj),
-        };
-        let array = match self.stack.pop().unwrap() {
-            Object::Array(v) => v,
-            obj => panic!("Expect Object::Array, get {:?}.", obj),
+        let index = self.pop();
+        let value = match (self.pop(), index) {
+            (Object::Array(array), Object::Int(index)) => match array_index(index, array.len()) {
+                Some(index) => (*array[index]).clone(),
+                None => NULL,
+            },
+            (Object::Str(s), Object::Int(index)) => match s.chars().nth(index as usize) {
+                Some(ch) => Object::Str(ch.to_string()),
+                None => NULL,
+            },
+            (Object::Hash(mut hash), key) => match key.hashable() {
+                Some(key) => hash.remove(&key).unwrap_or(NULL),
+                None => return self.halt(format!("Unusable as hash key: {}.", key)),
+            },
+            (obj, index) => return self.halt(format!("Unsupported index: {}[{}].", obj, index)),
         };
-        self.stack.push(match array.get(index as usize) {
-            Some(obj) => (**obj).clone(),
-            None => NULL,
-        });
+        self.stack.push(value);
     }
 
     fn execute_call(&mut self, num_args: usize) {
         let func = self.stack.remove(self.stack.len()-num_args-1);
-        let (instructions, num_locals, num_paras) = match func {
+        let (instructions, num_locals, num_paras) = match &func {
             Object::CompiledFunction { instructions, num_locals, num_paras } =>
-                (instructions, num_locals, num_paras),
-            obj => panic!("Expect Object::CompiledFunction, get {:?}.", obj),
+                (instructions.clone(), *num_locals, *num_paras),
+            Object::Builtin(name) => {
+                let arguments = self.stack.split_off(self.stack.len()-num_args);
+                return match call_builtin(name, arguments) {
+                    Object::Error(message) => self.halt(message),
+                    obj => self.stack.push(obj),
+                };
+            },
+            obj => return self.halt(format!("Not a function: {}.", obj)),
         };
-        assert_eq!(num_args, num_paras, "{} args vs {} paras", num_args, num_paras);
-        self.push_frame(instructions, self.stack.len()-num_args);
+        if num_args != num_paras {
+            return self.halt(format!("Wrong number of arguments: want {}, get {}.", num_paras, num_args));
+        }
+        if self.frames.len() >= self.max_frames {
+            return self.halt(String::from("Stack overflow."));
+        }
+        self.push_frame(func, instructions, self.stack.len()-num_args);
         for _ in 0..num_locals {
             self.stack.push(NULL);
         }
     }
 
     fn execute_return_value(&mut self) {
-        let value = self.stack.pop().unwrap();
-        self.pop_frame();
+        let value = self.pop();
+        if self.frames.is_empty() {
+            // A top-level return stops the program with the value as the result.
+            self.ip = self.instructions.len();
+        } else {
+            self.pop_frame();
+        }
         self.stack.push(value);
     }
 
     fn execute_return(&mut self) {
-        self.pop_frame();
+        if self.frames.is_empty() {
+            self.ip = self.instructions.len();
+        } else {
+            self.pop_frame();
+        }
         self.stack.push(NULL);
     }
 }
 
+// Pick the value the REPL shows from the result and the last popped value of VM::run.
+// The compiler pops every expression statement, so the result is only left on the stack
+// by a top-level return, and None means there is nothing to show.
+pub fn repl_value(result: Object, popped: Option<Object>) -> Option<Object> {
+    match (result, popped) {
+        (Object::Null, popped) => popped,
+        (result, _) => Some(result),
+    }
+}
+
+// Compile and run a program in a fresh VM, returning its result like the evaluator's `eval`.
+pub fn vm_eval(input: &str) -> Result<Object, String> {
+    let compiler = match Compiler::new(Parser::new(Lexer::new(input)), SymbolTable::new(None), vec!()) {
+        Ok(compiler) => compiler,
+        Err(errors) => {
+            let messages: Vec<String> = errors.iter().map(|err| err.to_string()).collect();
+            return Err(messages.join("\n"));
+        },
+    };
+    let bytecode = match compiler.compile_program() {
+        Ok(bytecode) => bytecode,
+        Err(err) => return Err(err.to_string()),
+    };
+    match VM::new(bytecode.instructions, bytecode.constants, HashMap::new()).run() {
+        Ok((result, popped, _globals)) => Ok(repl_value(result, popped).unwrap_or(NULL)),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
 
 #[cfg(test)]
 mod tests {
 
     use super::*;
+    use crate::code::SymbolTable;
+    use crate::object::HashKey;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use crate::compiler::Compiler;
+    use crate::evaluator::Evaluator;
+    use crate::object::Environment;
+
+    // Report a runtime error as an error object, so it fits in the test tables.
+    fn run(vm: VM) -> (Object, Option<Object>) {
+        match vm.run() {
+            Ok((result, popped, _globals)) => (result, popped),
+            Err(err) => (Object::Error(err.message), None),
+        }
+    }
 
     #[test]
     fn vm() {
@@ -260,46 +540,479 @@ mod tests {
             ("1 - 2;", NULL, Some(Object::Int(-1))),
             ("1 * 2;", NULL, Some(Object::Int(2))),
             ("1 / 2;", NULL, Some(Object::Int(0))),
+            ("5 % 3;", NULL, Some(Object::Int(2))),
+            ("1 / 0;", Object::Error(String::from("Division by zero.")), None),
+            ("fn() { 1 % 0; }();", Object::Error(String::from("Division by zero.")), None),
+            ("100000 * 100000;", NULL, Some(Object::Int(10000000000))),
+            ("2147483647 + 1;", NULL, Some(Object::Int(2147483648))),
+            ("4000000000 * 4000000000;", Object::Error(String::from("Integer overflow.")), None),
+            ("let f = fn(n) { if (n < 2) { 1 } else { n * f(n - 1) } }; f(20);",
+             NULL, Some(Object::Int(2432902008176640000))),
+            ("let f = fn(n) { if (n < 2) { 1 } else { n * f(n - 1) } }; f(21);",
+             Object::Error(String::from("Integer overflow.")), None),
+            ("9223372036854775807 + 1;", Object::Error(String::from("Integer overflow.")), None),
+            ("-9223372036854775807 - 2;", Object::Error(String::from("Integer overflow.")), None),
+            ("-9223372036854775808;", NULL, Some(Object::Int(i64::MIN))),
+            ("-(-9223372036854775808);", Object::Error(String::from("Integer overflow.")), None),
+            ("let a = 2; -(-a) + -(-(-3));", NULL, Some(Object::Int(-1))),
             ("1 == 2;", NULL, Some(Object::Bool(false))),
             ("1 != 2;", NULL, Some(Object::Bool(true))),
             ("1 > 2;", NULL, Some(Object::Bool(false))),
             ("1 < 2;", NULL, Some(Object::Bool(true))),
             ("true == true;", NULL, Some(Object::Bool(true))),
             ("true != true;", NULL, Some(Object::Bool(false))),
+            ("\"a\" == \"a\";", NULL, Some(Object::Bool(true))),
+            ("\"a\" != \"b\";", NULL, Some(Object::Bool(true))),
+            ("\"a\" == \"b\";", NULL, Some(Object::Bool(false))),
+            ("\"abc\" < \"abd\";", NULL, Some(Object::Bool(true))),
+            ("\"b\" > \"abc\";", NULL, Some(Object::Bool(true))),
+            ("\"ab\" > \"abc\";", NULL, Some(Object::Bool(false))),
+            ("[1, 2] + [3, 4];", NULL, Some(Object::Array(vec!(
+                Box::new(Object::Int(1)), Box::new(Object::Int(2)),
+                Box::new(Object::Int(3)), Box::new(Object::Int(4)),
+            )))),
+            ("let a = [1]; let b = [2]; let c = a + b; a;", NULL,
+             Some(Object::Array(vec!(Box::new(Object::Int(1)))))),
+            ("let a = [1]; let b = [2]; let c = a + b; b;", NULL,
+             Some(Object::Array(vec!(Box::new(Object::Int(2)))))),
+            ("!5;", NULL, Some(Object::Bool(false))),
+            ("!0;", NULL, Some(Object::Bool(false))),
+            ("!\"\";", NULL, Some(Object::Bool(false))),
+            ("!!5;", NULL, Some(Object::Bool(true))),
+            ("~0 == -1;", NULL, Some(Object::Bool(true))),
+            ("~5;", NULL, Some(Object::Int(-6))),
+            ("~true;", Object::Error(String::from("Expect Object::Int, get true.")), None),
+            ("let m = [[1, 2], [3, 4]]; m[1][0];", NULL, Some(Object::Int(3))),
+            ("{\"a\": [1, {\"b\": 5}]}[\"a\"][1][\"b\"];", NULL, Some(Object::Int(5))),
+            ("[[1]][0][1];", NULL, Some(NULL)),
+            ("let m = [[1, 2], [3, 4]]; let i = [1, 0]; m[i[0]][i[1] + 1];", NULL, Some(Object::Int(4))),
+            ("{ let x = 1; x + 1 };", NULL, Some(Object::Int(2))),
+            ("fn() { let a = { let x = 1; x * 3 }; a }();", NULL, Some(Object::Int(3))),
+            ("let x = 1; let a = { let x = 2; x * 3 }; a + x;", NULL, Some(Object::Int(7))),
+            ("fn() { let x = 1; let a = { let x = 2; x * 3 }; a + x }();", NULL, Some(Object::Int(7))),
+            ("let y = 1; { y = 2; }; y;", NULL, Some(Object::Int(2))),
+            ("{ 1; let y = 2; };", NULL, Some(NULL)),
+            ("if (true) { let y = 2; };", NULL, Some(NULL)),
+            ("let i = 0; while (i < 3) { i = i + 1; } i;", NULL, Some(Object::Int(3))),
+            ("let i = 0; while (false) { i = 1; } i;", NULL, Some(Object::Int(0))),
+            ("let s = 0; let i = 0; while (i < 4) { let j = i; while (j > 0) { s += j; j -= 1; } i += 1; } s;",
+             NULL, Some(Object::Int(10))),
+            ("fn(n) { let s = 0; while (n > 0) { s = s + n; n = n - 1; } s }(4);", NULL, Some(Object::Int(10))),
+            ("let f = fn() { while (true) { return 7; } }; f();", NULL, Some(Object::Int(7))),
+            ("let i = 0; do { i += 1; } while (false); i;", NULL, Some(Object::Int(1))),
+            ("let i = 0; do { i += 2; } while (i < 5); i;", NULL, Some(Object::Int(6))),
+            ("+5;", NULL, Some(Object::Int(5))),
+            ("1 - +2.5;", NULL, Some(Object::Float(-1.5))),
+            ("+true;", Object::Error(String::from("Expect Object::Int or Object::Float, get true.")), None),
+            ("let x = -1; x > 0 ? \"pos\" : \"neg\";", NULL, Some(Object::Str(String::from("neg")))),
+            ("let x = 0; x > 0 ? 1 : x < 0 ? -1 : 0;", NULL, Some(Object::Int(0))),
+            ("null;", NULL, Some(NULL)),
+            ("!null;", NULL, Some(Object::Bool(true))),
+            ("null == null;", NULL, Some(Object::Bool(true))),
+            ("null != null;", NULL, Some(Object::Bool(false))),
+            ("let x = 1; x == null;", NULL, Some(Object::Bool(false))),
+            ("[] != null;", NULL, Some(Object::Bool(true))),
+            ("first([]) == null;", NULL, Some(Object::Bool(true))),
+            ("null < 1;", Object::Error(String::from("Expect Object::Int, get Null.")), None),
+            ("6 & 3;", NULL, Some(Object::Int(2))),
+            ("6 | 3;", NULL, Some(Object::Int(7))),
+            ("6 ^ 3;", NULL, Some(Object::Int(5))),
+            ("1 << 4;", NULL, Some(Object::Int(16))),
+            ("-16 >> 2;", NULL, Some(Object::Int(-4))),
+            ("1 | 2 == 3;", NULL, Some(Object::Bool(true))),
+            ("1 << 32;", NULL, Some(Object::Int(4294967296))),
+            ("1 << 64;", Object::Error(String::from("Invalid shift amount 64.")), None),
+            ("1 >> -1;", Object::Error(String::from("Invalid shift amount -1.")), None),
+            ("1.0 & 1;", Object::Error(String::from("Unexpected arithmatic operator BitAnd.")), None),
+            ("\"a\" | \"b\";", Object::Error(String::from("Unexpected arithmatic operator BitOr.")), None),
+            ("\"ab\" * 3;", NULL, Some(Object::Str(String::from("ababab")))),
+            ("3 * \"ab\";", NULL, Some(Object::Str(String::from("ababab")))),
+            ("\"ab\" * -1;", NULL, Some(Object::Str(String::new()))),
+            ("\"ab\" * 9223372036854775807;", Object::Error(String::from("String too long.")), None),
+            ("1000000000 * \"ab\";", Object::Error(String::from("String too long.")), None),
+            ("\"\" * 9223372036854775807;", NULL, Some(Object::Str(String::new()))),
+            ("\"ab\" * 1.5;", Object::Error(String::from("Expect Object::Int, Object::Float, Object::Str or Object::Array, get 1.5.")), None),
+            ("1 + [2];", Object::Error(String::from("Expect Object::Array, get 1.")), None),
+            ("[1] + 2;", Object::Error(String::from("Expect Object::Int, get [1].")), None),
+            ("[1] - [2];", Object::Error(String::from("Unexpected arithmatic operator Sub.")), None),
+            ("1 < \"1\";", Object::Error(String::from("Expect Object::Str, get 1.")), None),
+            ("1 == \"1\";", Object::Error(String::from("Expect Object::Str, get 1.")), None),
+            ("\"1\" == 1;", Object::Error(String::from("Expect Object::Int, get 1.")), None),
+            ("true && false;", NULL, Some(Object::Bool(false))),
+            ("false || true;", NULL, Some(Object::Bool(true))),
+            ("false && 1 / 0;", NULL, Some(Object::Bool(false))),
+            ("true || 1 / 0;", NULL, Some(Object::Bool(true))),
+            ("true && 1 > 0;", NULL, Some(Object::Bool(true))),
+            ("false || false;", NULL, Some(Object::Bool(false))),
+            ("null && true;", Object::Error(String::from("Expect Object::Bool, get Null.")), None),
+            ("1 || false;", Object::Error(String::from("Expect Object::Bool, get 1.")), None),
             ("-1;", NULL, Some(Object::Int(-1))),
             ("!true;", NULL, Some(Object::Bool(false))),
             ("!(if (false) { 1 });", NULL, Some(Object::Bool(true))),
             ("if (true) { 1 } else {2};", NULL, Some(Object::Int(1))),
             ("if (false) { 1 };", NULL, Some(NULL)),
+            ("if (true) {};", NULL, Some(NULL)),
+            ("if (false) { 1 } else if (true) { 2 } else { 3 };", NULL, Some(Object::Int(2))),
             ("let a = 1; a + 1;", NULL, Some(Object::Int(2))),
+            ("let a = 1; a = a + 1; a;", NULL, Some(Object::Int(2))),
+            ("fn(a) { a = a + 1; a; }(1);", NULL, Some(Object::Int(2))),
             ("\"a\" + \"b\";", NULL, Some(Object::Str(String::from("ab")))),
             ("[1, 2];", NULL, Some(Object::Array(vec!(
                 Box::new(Object::Int(1)),
                 Box::new(Object::Int(2)),
             )))),
             ("[1, 2][1];", NULL, Some(Object::Int(2))),
+            ("[1, 2, 3][-1];", NULL, Some(Object::Int(3))),
+            ("1.5;", NULL, Some(Object::Float(1.5))),
+            ("-1.5;", NULL, Some(Object::Float(-1.5))),
+            ("1.5 + 1;", NULL, Some(Object::Float(2.5))),
+            ("3.0 / 2.0;", NULL, Some(Object::Float(1.5))),
+            ("2 * 1.5 - 1;", NULL, Some(Object::Float(2.0))),
+            ("1.0 / 0;", NULL, Some(Object::Float(f64::INFINITY))),
+            ("1.5 < 2;", NULL, Some(Object::Bool(true))),
+            ("1.5 > 2.5;", NULL, Some(Object::Bool(false))),
+            ("2.0 == 2;", NULL, Some(Object::Bool(true))),
+            ("1.5 != 1.5;", NULL, Some(Object::Bool(false))),
+            ("[1, 2, 3][-3];", NULL, Some(Object::Int(1))),
+            ("[1, 2, 3][-4];", NULL, Some(NULL)),
+            ("[1, 2, 3][3];", NULL, Some(NULL)),
+            ("\"abc\"[1];", NULL, Some(Object::Str(String::from("b")))),
+            ("\"abc\"[3];", NULL, Some(NULL)),
+            ("\"abc\"[-1];", NULL, Some(NULL)),
+            ("{};", NULL, Some(Object::Hash(HashMap::new()))),
+            ("let k = \"b\"; {1: 2, k: 1 + 2, true: \"t\", 1: 4};", NULL, Some(Object::Hash(HashMap::from([
+                (HashKey::Int(1), Object::Int(4)),
+                (HashKey::Str(String::from("b")), Object::Int(3)),
+                (HashKey::Bool(true), Object::Str(String::from("t"))),
+            ])))),
+            ("{[]: 1};", Object::Error(String::from("Unusable as hash key: [].")), None),
+            ("{1: 2}[fn() {}];", Object::Error(String::from("Unusable as hash key: compiled function.")), None),
+            ("{\"a\": 1}[\"a\"];", NULL, Some(Object::Int(1))),
+            ("{\"a\": 1}[\"b\"];", NULL, Some(NULL)),
             ("fn() { return 1; }();", NULL, Some(Object::Int(1))),
             ("fn() { 1; }();", NULL, Some(Object::Int(1))),
             ("fn() {}();", NULL, Some(NULL)),
+            ("return 10; 5;", Object::Int(10), None),
+            ("len([1, 2, 3]);", NULL, Some(Object::Int(3))),
+            ("let f = fn(a) { push(a, len(a)) }; f([1]);", NULL,
+             Some(Object::Array(vec!(Box::new(Object::Int(1)), Box::new(Object::Int(1)))))),
+            ("let f = len; f(\"ab\");", NULL, Some(Object::Int(2))),
+            ("let len = fn(x) { 0 }; len(\"abc\");", NULL, Some(Object::Int(0))),
+            ("len(1);", Object::Error(String::from("len expects Object::Str or Object::Array, get 1.")), None),
+            ("puts(1);", NULL, Some(NULL)),
+            ("let fib = fn(n) { if (n < 2) { n } else { fib(n - 1) + fib(n - 2) } }; fib(6);",
+             NULL, Some(Object::Int(8))),
+            ("fn() { let f = fn(n) { if (n > 0) { f(n - 1) } else { 10 } }; f(3); }();",
+             NULL, Some(Object::Int(10))),
+            ("let f = fn(n) { let g = fn() { f(0) }; if (n > 0) { g() } else { 42 } }; f(1);",
+             NULL, Some(Object::Int(42))),
+            ("let f = fn(n) { let g = fn(m) { if (m > 0) { f(m - 1) } else { 7 } }; g(n) }; f(3);",
+             NULL, Some(Object::Int(7))),
+            ("1; return 2 * 5; 3;", Object::Int(10), Some(Object::Int(1))),
+            ("let f = fn() { f() }; f();", Object::Error(String::from("Stack overflow.")), None),
             ("
                 let a = 1; 
                 let b = fn() { let a = 2; a; }();
                 a + b;
             ", NULL, Some(Object::Int(3))),
             ("fn(a) { a; }(1);", NULL, Some(Object::Int(1))),
+            ("let x = 1; x += 4; x -= 1; x *= 3; x /= 2; x;", NULL, Some(Object::Int(6))),
+            ("fn(x) { x += 1; x }(1);", NULL, Some(Object::Int(2))),
         ];
         for (input, result, popped) in test_array.iter() {
             let lexer = Lexer::new(input);
             let parser = Parser::new(lexer);
             let symbol_table = SymbolTable::new(None);
-            let compiler = Compiler::new(parser, symbol_table);
-            let (code, _symbol_table) = compiler.run();
+            let compiler = Compiler::new(parser, symbol_table, vec!()).unwrap();
+            let (code, _symbol_table, constants) = compiler.run().unwrap();
             let globals = HashMap::new();
-            let vm = VM::new(code, globals);
-            let (r, p, _g) = vm.run();
+            let vm = VM::new(code, constants, globals);
+            let (r, p) = run(vm);
             println!("VM: {:?} - {:?} - {:?}", input, r, p);
             assert_eq!(result, &r);
             assert_eq!(popped, &p);
         }
     }
+
+    #[test]
+    fn vm_recursion() {
+        let input = "let fib = fn(n) { if (n < 2) { n } else { fib(n - 1) + fib(n - 2) } }; fib(20);";
+        let lexer = Lexer::new(input);
+        let parser = Parser::new(lexer);
+        let compiler = Compiler::new(parser, SymbolTable::new(None), vec!()).unwrap();
+        let (code, _symbol_table, constants) = compiler.run().unwrap();
+        let vm = VM::new(code, constants.clone(), HashMap::new());
+        let (_r, p, g) = vm.run().unwrap();
+        assert_eq!(p, Some(Object::Int(6765)));
+        // The function in the globals still shares its instructions with the constant pool.
+        match (&constants[2], g.get(&0)) {
+            (Object::CompiledFunction { instructions: c, .. },
+             Some(Object::CompiledFunction { instructions: g, .. })) => {
+                assert!(Rc::ptr_eq(c, g));
+                assert_eq!(Rc::strong_count(c), 2);
+            },
+            (c, g) => panic!("Expect Object::CompiledFunction, get {:?} and {:?}.", c, g),
+        }
+    }
+
+    #[test]
+    fn vm_limits() {
+        let input = "let f = fn(n) { if (n > 0) { f(n - 1) } else { 0 } };";
+        let lexer = Lexer::new(input);
+        let parser = Parser::new(lexer);
+        let compiler = Compiler::new(parser, SymbolTable::new(None), vec!()).unwrap();
+        let (code, symbol_table, constants) = compiler.run().unwrap();
+        let (_r, _p, globals) = VM::new(code, constants.clone(), HashMap::new()).run().unwrap();
+        let test_array = [
+            ("f(3);", NULL, Some(Object::Int(0))),
+            ("f(4);", Object::Error(String::from("Stack overflow.")), None),
+        ];
+        for (input, result, popped) in test_array.iter() {
+            let lexer = Lexer::new(input);
+            let parser = Parser::new(lexer);
+            let compiler = Compiler::new(parser, symbol_table.clone(), constants.clone()).unwrap();
+            let (code, _symbol_table, constants) = compiler.run().unwrap();
+            let vm = VM::with_limits(code, constants, globals.clone(), 4);
+            let (r, p) = run(vm);
+            println!("VM: {:?} - {:?} - {:?}", input, r, p);
+            assert_eq!(result, &r);
+            assert_eq!(popped, &p);
+        }
+    }
+
+    #[test]
+    fn vm_repl() {
+        // Every line is compiled and run on its own, like in the REPL.
+        let test_array = [
+            ("let add = fn(a, b) { a + b };", NULL, None),
+            ("let s = \"a\";", NULL, None),
+            ("add(1, 2);", NULL, Some(Object::Int(3))),
+            ("let twice = fn(x) { add(x, x) }; twice(s);", NULL, Some(Object::Str(String::from("aa")))),
+            ("twice(add(1, 2));", NULL, Some(Object::Int(6))),
+        ];
+        let mut symbol_table = SymbolTable::new(None);
+        let mut constants = vec!();
+        let mut globals = HashMap::new();
+        for (input, result, popped) in test_array.iter() {
+            let lexer = Lexer::new(input);
+            let parser = Parser::new(lexer);
+            let compiler = Compiler::new(parser, symbol_table, constants).unwrap();
+            let (code, sym_table, consts) = compiler.run().unwrap();
+            let vm = VM::new(code, consts.clone(), globals);
+            let (r, p, g) = vm.run().unwrap();
+            println!("VM: {:?} - {:?} - {:?}", input, r, p);
+            assert_eq!(result, &r);
+            assert_eq!(popped, &p);
+            symbol_table = sym_table;
+            constants = consts;
+            globals = g;
+        }
+    }
+
+    #[test]
+    fn vm_truthiness() {
+        // Both backends must agree on which values are truthy.
+        let test_array = [
+            ("0", true),
+            ("\"\"", true),
+            ("[]", true),
+            ("{}", true),
+            ("if (false) { 1 }", false),
+            ("false", false),
+            ("true", true),
+        ];
+        for (value, truthy) in test_array.iter() {
+            let expected = Object::Int(if *truthy { 1 } else { 2 });
+            for input in [format!("if ({}) {{ 1 }} else {{ 2 }};", value),
+                          format!("if (!!({})) {{ 1 }} else {{ 2 }};", value)].iter() {
+                let evaluator = Evaluator::new(Parser::new(Lexer::new(input)), Environment::new()).unwrap();
+                let (e, _env) = evaluator.last().unwrap();
+                let compiler = Compiler::new(Parser::new(Lexer::new(input)), SymbolTable::new(None), vec!()).unwrap();
+                let (code, _symbol_table, constants) = compiler.run().unwrap();
+                let (_r, p, _g) = VM::new(code, constants, HashMap::new()).run().unwrap();
+                println!("Truthiness: {:?} - {:?} - {:?}", input, e, p);
+                assert_eq!(e, expected);
+                assert_eq!(p, Some(expected.clone()));
+            }
+        }
+    }
+
+    #[test]
+    fn vm_builtins() {
+        // Both backends must agree on what the builtins return.
+        let test_array = [
+            "len(\"hello\");",
+            "len([1, 2, 3]);",
+            "len(1);",
+            "len(\"a\", \"b\");",
+            "first([1, 2]);",
+            "first([]);",
+            "first(1);",
+            "last([1, 2]);",
+            "rest([1, 2, 3]);",
+            "rest([]);",
+            "rest([1]);",
+            "last([]);",
+            "push([1], 2);",
+            "push(1, 2);",
+            "push([1]);",
+            "puts(1, \"a\");",
+            "type(len);",
+            "str(int(\"7\") * 2);",
+            "int(\"x\");",
+            "range(3);",
+            "range(2, 5);",
+            "range(5, 2);",
+            "range(0, 9223372036854775807);",
+            "len(range(1.5));",
+            "join([\"a\", \"b\"], \", \");",
+            "join([1], \"\");",
+            "contains([1, 2], 2);",
+            "contains({1: 2}, 2);",
+            "contains(1, 2);",
+            "abs(-3);",
+            "min(3, 1, 2);",
+            "max(3, 1.5, 2);",
+            "max();",
+        ];
+        for input in test_array.iter() {
+            let evaluator = Evaluator::new(Parser::new(Lexer::new(input)), Environment::new()).unwrap();
+            let (e, _env) = evaluator.last().unwrap();
+            let compiler = Compiler::new(Parser::new(Lexer::new(input)), SymbolTable::new(None), vec!()).unwrap();
+            let (code, _symbol_table, constants) = compiler.run().unwrap();
+            let (r, p) = run(VM::new(code, constants, HashMap::new()));
+            println!("Builtins: {:?} - {:?} - {:?} - {:?}", input, e, r, p);
+            match e {
+                Object::Error(_) => assert_eq!(r, e),
+                e => assert_eq!(p, Some(e)),
+            }
+        }
+    }
+
+    #[test]
+    fn vm_dup() {
+        let constants = vec!(Object::Int(7));
+        let code = vec!(Code::Constant(0), Code::Dup, Code::Add);
+        let (r, _p, _g) = VM::new(code, constants.clone(), HashMap::new()).run().unwrap();
+        assert_eq!(r, Object::Int(14));
+        let code = vec!(Code::Constant(0), Code::Dup, Code::Equal);
+        let (r, _p, _g) = VM::new(code, constants, HashMap::new()).run().unwrap();
+        assert_eq!(r, Object::Bool(true));
+    }
+
+    #[test]
+    fn vm_call() {
+        let test_array = [
+            ("1();", Object::Error(String::from("Not a function: 1."))),
+            ("let a = \"f\"; a(1, 2);", Object::Error(String::from("Not a function: f."))),
+            ("fn(a) { a }();", Object::Error(String::from("Wrong number of arguments: want 1, get 0."))),
+            ("fn() { 1 }(2, 3);", Object::Error(String::from("Wrong number of arguments: want 0, get 2."))),
+            ("let f = fn(a) { a }; fn() { f() }();",
+             Object::Error(String::from("Wrong number of arguments: want 1, get 0."))),
+        ];
+        for (input, result) in test_array.iter() {
+            let compiler = Compiler::new(Parser::new(Lexer::new(input)), SymbolTable::new(None), vec!()).unwrap();
+            let (code, _symbol_table, constants) = compiler.run().unwrap();
+            let (r, _p) = run(VM::new(code, constants, HashMap::new()));
+            println!("VM: {:?} - {:?}", input, r);
+            assert_eq!(result, &r);
+        }
+    }
+
+    #[test]
+    fn vm_repl_value() {
+        let test_array = [
+            ("1 + 2;", Some(Object::Int(3))),
+            ("let a = 1;", None),
+            ("1; let a = 2;", Some(Object::Int(1))),
+            ("return 4; 5;", Some(Object::Int(4))),
+            ("null;", Some(NULL)),
+        ];
+        for (input, expected) in test_array.iter() {
+            let compiler = Compiler::new(Parser::new(Lexer::new(input)), SymbolTable::new(None), vec!()).unwrap();
+            let (code, _symbol_table, constants) = compiler.run().unwrap();
+            let (r, p, _g) = VM::new(code, constants, HashMap::new()).run().unwrap();
+            println!("VM: {:?} - {:?} - {:?}", input, r, p);
+            assert_eq!(&repl_value(r, p), expected);
+        }
+    }
+
+    #[test]
+    fn vm_eval() {
+        // Both backends must agree on the results and the formatted errors.
+        let test_array = [
+            "let a = 1; a + 2;",
+            "let a = 1;",
+            "1; return 2; 3;",
+            "1(); 2;",
+            "let = 1; let b 2;",
+            "-9223372036854775808;",
+            "-(-9223372036854775808);",
+            "let a = 5; -(-a) - -3;",
+        ];
+        for input in test_array.iter() {
+            let result = super::vm_eval(input);
+            println!("VM: {:?} - {:?}", input, result);
+            assert_eq!(result, crate::evaluator::eval(input));
+        }
+        assert_eq!(super::vm_eval("1; a;"), Err(String::from("Compile error: Identifier a not found.")));
+    }
+
+    #[test]
+    fn vm_globals() {
+        // Hand-written bytecode may read a global before setting it.
+        let test_array = [
+            (vec!(Code::GetGlobal(0)), Object::Error(String::from("Global 0 is not initialized."))),
+            (vec!(Code::Constant(0), Code::SetGlobal(1), Code::GetGlobal(0), Code::Constant(0)),
+             Object::Error(String::from("Global 0 is not initialized."))),
+            (vec!(Code::Constant(0), Code::SetGlobal(0), Code::GetGlobal(0)), Object::Int(7)),
+        ];
+        for (code, result) in test_array.iter() {
+            let (r, _p) = run(VM::new(code.clone(), vec!(Object::Int(7)), HashMap::new()));
+            println!("VM: {:?} - {:?}", code, r);
+            assert_eq!(result, &r);
+        }
+    }
+
+    #[test]
+    fn vm_runtime_error() {
+        let test_array = [
+            ("1 + true;", "Expect Object::Int, Object::Float, Object::Str or Object::Array, get true."),
+            ("1[0];", "Unsupported index: 1[0]."),
+            ("[1][\"a\"];", "Unsupported index: [1][a]."),
+            ("-\"a\";", "Expect Object::Int or Object::Float, get a."),
+        ];
+        for (input, message) in test_array.iter() {
+            let compiler = Compiler::new(Parser::new(Lexer::new(input)), SymbolTable::new(None), vec!()).unwrap();
+            let (code, _symbol_table, constants) = compiler.run().unwrap();
+            let result = VM::new(code, constants, HashMap::new()).run();
+            println!("VM: {:?} - {:?}", input, result);
+            assert_eq!(result, Err(RuntimeError { message: String::from(*message) }));
+        }
+        // Malformed bytecode is reported instead of panicking.
+        let test_array = [
+            (vec!(Code::Constant(0), Code::Constant(0), Code::GreaterThan), Object::Bool(false)),
+            (vec!(Code::Constant(0), Code::Constant(0), Code::And),
+             Object::Error(String::from("Unknown operator And."))),
+            (vec!(Code::Call(0)), Object::Error(String::from("Stack underflow at Call 0."))),
+            (vec!(Code::Add), Object::Error(String::from("Stack underflow at Add."))),
+            (vec!(Code::Constant(0), Code::Equal), Object::Error(String::from("Stack underflow at Equal."))),
+            (vec!(Code::Constant(0), Code::Index), Object::Error(String::from("Stack underflow at Index."))),
+            (vec!(Code::Minus), Object::Error(String::from("Stack underflow at Minus."))),
+            (vec!(Code::Constant(0), Code::Array(2)), Object::Error(String::from("Stack underflow at Array 2."))),
+            (vec!(Code::Constant(0), Code::Hash(1)), Object::Error(String::from("Stack underflow at Hash 1."))),
+            (vec!(Code::GetLocal(0)), Object::Error(String::from("Stack underflow at GetLocal 0."))),
+            (vec!(Code::Constant(0), Code::SetLocal(0)), Object::Error(String::from("Stack underflow at SetLocal 0."))),
+            (vec!(Code::JumpNotTruthy(0)), Object::Error(String::from("Stack underflow at JumpNotTruthy 0000."))),
+            (vec!(Code::Constant(9)), Object::Error(String::from("Constant 9 does not exist."))),
+            (vec!(Code::GetBuiltin(99)), Object::Error(String::from("Builtin 99 does not exist."))),
+            (vec!(Code::CurrentFunction),
+             Object::Error(String::from("No current function outside of a function."))),
+        ];
+        for (code, result) in test_array.iter() {
+            let (r, _p) = run(VM::new(code.clone(), vec!(Object::Int(7)), HashMap::new()));
+            println!("VM: {:?} - {:?}", code, r);
+            assert_eq!(result, &r);
+        }
+    }
 }